@@ -0,0 +1,145 @@
+//! Contains the ring of backups kept of the events file.
+//!
+//! A backup is taken at the start of every session in which the events changed, keeping the last
+//! `backup_sessions` of them. The first backup of each day is also kept as a daily snapshot, for
+//! the last `backup_days` days. Backups are named after when they were taken, as in
+//! `session-20211231T235959.json` and `daily-20211231T235959.json`.
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use clamendar::{storage::json, Event};
+use crate::{config::{Backend, Config}, deserialize, error::Error, sync};
+use std::{
+    cmp::Reverse,
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+
+const STAMP: &str = "%Y%m%dT%H%M%S";
+
+/// Whether a backup was taken for a session or as the day's snapshot.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Kind {
+    Daily,
+    Session,
+}
+
+/// A backup file found in the backup directory.
+pub struct Backup {
+    /// The number of events in the backup, or None if it cannot be read.
+    pub count: Option<usize>,
+    pub kind: Kind,
+    pub path: PathBuf,
+    pub taken: DateTime<Local>,
+}
+
+impl Backup {
+    /// Recognizes a backup by its file name.
+    fn from_path(path: PathBuf) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.strip_suffix(".json")?;
+        let (kind, stamp) = match name.split_once('-')? {
+            ("daily", stamp) => (Kind::Daily, stamp),
+            ("session", stamp) => (Kind::Session, stamp),
+            _ => return None,
+        };
+        let taken = Local.from_local_datetime(&NaiveDateTime::parse_from_str(stamp, STAMP).ok()?).earliest()?;
+        let count = deserialize(&path).ok().map(|events| events.len());
+        Some(Backup { count, kind, path, taken })
+    }
+
+    /// Describes the backup in one line, comparing its number of events to `current`.
+    pub fn summary(&self, current: usize) -> String {
+        let kind = match self.kind {
+            Kind::Daily => "daily",
+            Kind::Session => "session",
+        };
+        let count = match self.count {
+            Some(count) => {
+                let noun = if count == 1 { "event" } else { "events" };
+                match count {
+                    count if count > current => format!("{} {} ({} more than now)", count, noun, count - current),
+                    count if count < current => format!("{} {} ({} fewer than now)", count, noun, current - count),
+                    count => format!("{} {} (as many as now)", count, noun),
+                }
+            },
+            None => "unreadable".to_string(),
+        };
+        format!("{}  {:<7}  {}", self.taken.format("%F %T"), kind, count)
+    }
+}
+
+/// Lists the backups, most recent first.
+pub fn list(config: &Config) -> Result<Vec<Backup>, Error> {
+    let dir = match &config.backup_dir {
+        Some(dir) => dir,
+        None => return Ok(Vec::new()),
+    };
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(Error::Io(error)),
+    };
+    let mut backups = Vec::new();
+    for entry in entries {
+        if let Some(backup) = Backup::from_path(entry?.path()) { backups.push(backup); }
+    }
+    backups.sort_unstable_by_key(|backup| Reverse(backup.taken));
+    Ok(backups)
+}
+
+/// Replaces the events with those in a backup. The current events are backed up first, so that
+/// restoring can be undone.
+pub fn restore(config: &Config, backup: &Backup) -> Result<Vec<Event>, Error> {
+    let events = deserialize(&backup.path)?;
+    let _lock = sync::lock(&config.events_file)?;
+    rotate(config)?;
+    config.storage()?.save(&events.iter().collect::<Vec<&Event>>())?;
+    Ok(events)
+}
+
+/// Backs up the events file unless it is unchanged since the last session's backup, then drops
+/// the oldest backups beyond those configured.
+pub fn rotate(config: &Config) -> Result<(), Error> {
+    let dir = match &config.backup_dir {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    fs::create_dir_all(dir)?;
+    let backups = list(config)?;
+    let now = Local::now();
+    let current = match config.storage {
+        Backend::Json => match fs::read(&config.events_file) {
+            Ok(current) => current,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(Error::Io(error)),
+        },
+        // a database is backed up as JSON, so that backups read the same whatever the storage
+        Backend::Sqlite => json::encode(&config.storage()?.load()?.iter().collect::<Vec<&Event>>())?,
+    };
+    let latest = backups.iter().find(|backup| backup.kind == Kind::Session);
+    if latest.is_none_or(|backup| fs::read(&backup.path).ok().as_ref() != Some(&current)) {
+        fs::write(dir.join(format!("session-{}.json", now.format(STAMP))), &current)?;
+    }
+    if !backups.iter().any(|backup| backup.kind == Kind::Daily && backup.taken.date() == now.date()) {
+        fs::write(dir.join(format!("daily-{}.json", now.format(STAMP))), &current)?;
+    }
+    // the listing is taken again to include the backups just written
+    let backups = list(config)?;
+    prune(&backups, Kind::Session, config.backup_sessions)?;
+    prune(&backups, Kind::Daily, config.backup_days)
+}
+
+/// Removes all but the `keep` most recent backups of the given kind.
+fn prune(backups: &[Backup], kind: Kind, keep: usize) -> Result<(), Error> {
+    for backup in backups.iter().filter(|backup| backup.kind == kind).skip(keep) {
+        remove(&backup.path)?;
+    }
+    Ok(())
+}
+
+fn remove(path: &Path) -> Result<(), Error> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(Error::Io(error)),
+        _ => Ok(()),
+    }
+}
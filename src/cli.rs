@@ -0,0 +1,333 @@
+//! Contains the commands that work on the events without opening the interface, for scripts.
+//!
+//! Each command reads the events under the lock on the events file and goes through a State, just
+//! as the interface does, so that events are entered and changed the same way in both. The agenda
+//! only reads the events it shows, through `Storage::between` and `Storage::outstanding`. Events are
+//! listed in the order of `Ord for Event`, ties broken by id, by default one per line as their id,
+//! a tab, and the record the interface would yank them to, which can be given back to `add` or
+//! `edit` as it is.
+
+use chrono::{Date, DateTime, Duration, Local, Timelike};
+use clamendar::{filter::{Filter, Kind}, ical, storage::Storage, zone::Zone, Event, Interval};
+use crate::{
+    backup,
+    config::{Config, Field, Format, Listing, Pane},
+    end_time,
+    error::Error,
+    event_to_record,
+    load,
+    start_time,
+    state::{Edit, State},
+    sync::{self, Lock},
+};
+use serde_json::{Map, Value};
+use std::{fs, io::{self, Write}, iter, mem, path::Path};
+use uuid::Uuid;
+
+/// Every field, as listed in JSON by default. The keys of JSON objects come in alphabetical order.
+const ALL_FIELDS: [Field; 7] = [Field::Id, Field::Iso, Field::Description, Field::Start, Field::End, Field::Kind, Field::Zone];
+
+/// The events as they are on disk, held under the lock until written back.
+struct Session {
+    s: State,
+    storage: Box<dyn Storage>,
+    // declared last, so that it is released last
+    _lock: Lock,
+}
+
+impl Session {
+    /// Reads the events, creating or upgrading the events file first if needed.
+    fn open(config: &Config) -> Result<Self, Error> {
+        load(config, false)?;
+        let lock = sync::lock(&config.events_file)?;
+        let mut storage = config.storage()?;
+        let mut s = State::default();
+        s.replace_events(sync::read(storage.as_mut())?);
+        Ok(Session { s, storage, _lock: lock })
+    }
+
+    /// Writes back the event with the given id, which is new to the store unless `existing`, or
+    /// removes it from the store if it is no longer among the events.
+    fn store(&mut self, id: Uuid, existing: bool) -> Result<(), Error> {
+        match (self.s.find(id), existing) {
+            (Some(event), false) => self.storage.insert(event)?,
+            (Some(event), true) => self.storage.update(event)?,
+            (None, true) => self.storage.delete(id)?,
+            (None, false) => {},
+        }
+        Ok(())
+    }
+}
+
+/// Adds the event described by `record` and prints its id.
+pub fn add(config: &Config, record: &str) -> Result<(), Error> {
+    let mut session = Session::open(config)?;
+    session.s.buffer = record.to_string();
+    let id = session.s.add_event_from_buffer()?;
+    session.store(id, false)?;
+    writeln!(io::stdout().lock(), "{}", id)?;
+    Ok(())
+}
+
+/// Prints every occurrence of an event and every interval from today on, for `days` days, under a
+/// heading for each day that has any. Single events that have passed are flagged as overdue, and
+/// those from before today come first. Untimed events come last. Only events matching the filter,
+/// if any, are printed.
+pub fn agenda(config: &Config, days: u32, filter: Option<&Filter>) -> Result<(), Error> {
+    load(config, false)?;
+    let _lock = sync::lock(&config.events_file)?;
+    let mut storage = config.storage()?;
+    let keep = |event: &&Event| filter.is_none_or(|filter| filter.matches(event));
+    let now = Local::now();
+    let today = now.date();
+    let (from, to) = (today.and_hms(0, 0, 0), (today + Duration::days(days.into())).and_hms(0, 0, 0));
+    let (intervals, timed): (Vec<Event>, Vec<Event>) = sync::found(storage.between(from, to))?
+        .into_iter().partition(|event| matches!(event.interval, Interval::Standard(_)));
+    // overdue single events and untimed ones stay due until deleted, whatever the window
+    let mut outstanding = sync::found(storage.outstanding(from))?;
+    outstanding.sort();
+    let (overdue, untimed): (Vec<Event>, Vec<Event>) =
+        outstanding.into_iter().partition(|event| event.start.is_some());
+    // each entry is the day it is listed under, the time it is sorted by within it, and its line
+    let mut entries: Vec<(Date<Local>, DateTime<Local>, String)> = Vec::new();
+    for event in timed.iter().filter(keep) {
+        for (start, _) in event.occurrences(from, to) {
+            let description = event.overrides
+                .iter()
+                .find(|replacement| replacement.start == start)
+                .map_or(&event.description, |replacement| &replacement.description);
+            let overdue = if matches!(event.interval, Interval::None) && start < now { " (overdue)" } else { "" };
+            entries.push((start.date(), start, format!("  {:<5}  {}{}", clock(start), description, overdue)));
+        }
+    }
+    // an interval is listed under every day it overlaps, showing its start on the first
+    for event in intervals.iter().filter(keep) {
+        for (start, end) in event.occurrences(from, to) {
+            let until = end_time(event, config).map(|end| format!(" (until {})", end)).unwrap_or_default();
+            let mut day = start.date().max(today);
+            while day.and_hms(0, 0, 0) < end.min(to) {
+                let (time, shown) = if day == start.date() { (start, clock(start)) } else { (day.and_hms(0, 0, 0), String::new()) };
+                entries.push((day, time, format!("  {:<5}  {}{}", shown, event.description, until)));
+                day = day.succ();
+            }
+        }
+    }
+    entries.sort_by_key(|&(day, time, _)| (day, time));
+
+    let mut out = io::stdout().lock();
+    let overdue: Vec<&Event> = overdue.iter().filter(keep).collect();
+    if !overdue.is_empty() {
+        writeln!(out, "Overdue")?;
+        for event in overdue {
+            writeln!(out, "  {}  {}", start_time(event, config).unwrap_or_default(), event.description)?;
+        }
+    }
+    let mut heading = None;
+    for (day, _, line) in entries {
+        if heading != Some(day) {
+            let label = match (day - today).num_days() {
+                0 => "Today, ",
+                1 => "Tomorrow, ",
+                _ => "",
+            };
+            writeln!(out, "{}{}", label, day.format(&format!("%a {}", config.date_format)))?;
+            heading = Some(day);
+        }
+        writeln!(out, "{}", line)?;
+    }
+    let untimed: Vec<&Event> = untimed.iter().filter(keep).collect();
+    if !untimed.is_empty() {
+        writeln!(out, "Untimed")?;
+        for event in untimed {
+            writeln!(out, "  {}", event.description)?;
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the event whose id is or starts with `id`, every occurrence included.
+pub fn delete(config: &Config, id: &str) -> Result<(), Error> {
+    let mut session = Session::open(config)?;
+    let id = identify(&session.s, id)?;
+    session.s.remove(id);
+    session.store(id, true)
+}
+
+/// Replaces the event whose id is or starts with `id` with the one described by `record`, keeping
+/// its id. Without a record, prints the event's instead.
+pub fn edit(config: &Config, id: &str, record: Option<&str>) -> Result<(), Error> {
+    let mut session = Session::open(config)?;
+    let id = identify(&session.s, id)?;
+    let mut event = session.s.remove(id).ok_or_else(|| Error::NoSuchEvent(id.to_string()))?;
+    let record = match record {
+        Some(record) => record,
+        None => {
+            writeln!(io::stdout().lock(), "{}", event_to_record(&event))?;
+            return Ok(());
+        },
+    };
+    // skipped and replaced occurrences carry over, as when yanking all occurrences
+    session.s.editing = Some(Edit::All {
+        id,
+        exceptions: mem::take(&mut event.exceptions),
+        overrides: mem::take(&mut event.overrides),
+    });
+    session.s.buffer = record.to_string();
+    session.s.add_event_from_buffer()?;
+    session.store(id, true)
+}
+
+/// Writes every event to `path` as iCalendar, or prints them without one.
+pub fn export(config: &Config, path: Option<&Path>) -> Result<(), Error> {
+    let events = config.storage()?.load()?;
+    let calendar = ical::export(&events.iter().collect::<Vec<&Event>>());
+    match path {
+        Some(path) => fs::write(path, calendar)?,
+        None => write!(io::stdout().lock(), "{}", calendar)?,
+    }
+    Ok(())
+}
+
+/// Adds the events in the iCalendar file at `path` that are not already present, reporting what
+/// could not be represented. The events are backed up first if any are added.
+pub fn import(config: &Config, path: &Path) -> Result<(), Error> {
+    let calendar = fs::read_to_string(path)?;
+    let mut session = Session::open(config)?;
+    let import = ical::import(&calendar, &session.s.events());
+    for problem in &import.problems {
+        eprintln!("{}", problem);
+    }
+    let imported = import.events.len();
+    if imported > 0 {
+        // importing can be undone by restoring the backup taken here
+        backup::rotate(config)?;
+        let mut events: Vec<&Event> = session.s.events();
+        events.extend(&import.events);
+        session.storage.save(&events)?;
+    }
+    writeln!(io::stdout().lock(), "imported {} events; skipped {} already present", imported, import.duplicates)?;
+    Ok(())
+}
+
+/// Prints the events as asked. Searching matches the description of an event or of one of its
+/// replaced occurrences.
+pub fn list(config: &Config, listing: &Listing) -> Result<(), Error> {
+    let session = Session::open(config)?;
+    let mut events: Vec<&Event> = match listing.pane {
+        Some(Pane::Intervals) => session.s.intervals.iter().collect(),
+        Some(Pane::Timed) => session.s.timed.iter().collect(),
+        Some(Pane::Untimed) => session.s.untimed.iter().collect(),
+        None => session.s.events(),
+    };
+    if let Some(text) = &listing.text {
+        let text = text.to_lowercase();
+        let contains = |description: &str| description.to_lowercase().contains(&text);
+        events.retain(|event| contains(&event.description) || event.overrides.iter().any(|replacement| contains(&replacement.description)));
+    }
+    if let Some(filter) = &listing.filter {
+        events.retain(|event| filter.matches(event));
+    }
+    events.sort_by(|a, b| a.cmp(b).then(a.id.cmp(&b.id)));
+    print(&events, listing, config)
+}
+
+/// Returns the time of day of an agenda entry, or nothing at midnight, where an event without a
+/// time of its own starts.
+fn clock(time: DateTime<Local>) -> String {
+    if time.num_seconds_from_midnight() == 0 { String::new() } else { time.format("%R").to_string() }
+}
+
+/// Finds the event whose id is or starts with `prefix`.
+fn identify(s: &State, prefix: &str) -> Result<Uuid, Error> {
+    let prefix = prefix.to_lowercase();
+    if prefix.is_empty() { return Err(Error::NoSuchEvent(prefix)); }
+    let mut matches = s.events().into_iter().filter(|event| event.id.to_string().starts_with(&prefix));
+    match (matches.next(), matches.next()) {
+        (Some(event), None) => Ok(event.id),
+        (Some(_), Some(_)) => Err(Error::AmbiguousEvent(prefix)),
+        (None, _) => Err(Error::NoSuchEvent(prefix)),
+    }
+}
+
+/// Returns the name of a field, as given to `--fields`.
+fn name(field: Field) -> &'static str {
+    match field {
+        Field::Description => "description",
+        Field::End => "end",
+        Field::Id => "id",
+        Field::Iso => "iso",
+        Field::Kind => "kind",
+        Field::Start => "start",
+        Field::Zone => "zone",
+    }
+}
+
+/// Writes the events to standard output in the format asked for.
+fn print(events: &[&Event], listing: &Listing, config: &Config) -> Result<(), Error> {
+    let mut out = io::stdout().lock();
+    let fields: &[Field] = match (&listing.fields, listing.format) {
+        (Some(fields), _) => fields,
+        (None, Format::Json) | (None, Format::JsonLines) => &ALL_FIELDS,
+        (None, Format::Table) => &[Field::Id, Field::Start, Field::End, Field::Description],
+        (None, Format::Tsv) => &[Field::Id, Field::Iso, Field::Description],
+    };
+    let human = listing.format == Format::Table;
+    let rows: Vec<Vec<Option<String>>> = events
+        .iter()
+        .map(|event| fields.iter().map(|&field| value(event, field, config, human)).collect())
+        .collect();
+    let object = |row: Vec<Option<String>>| -> Value {
+        let pairs = fields.iter().zip(row).map(|(&field, value)| (name(field).to_string(), value.map_or(Value::Null, Value::String)));
+        Value::Object(pairs.collect::<Map<_, _>>())
+    };
+    match listing.format {
+        Format::Json => writeln!(out, "{}", Value::Array(rows.into_iter().map(object).collect()))?,
+        Format::JsonLines => for row in rows {
+            writeln!(out, "{}", object(row))?;
+        },
+        Format::Table => {
+            let header: Vec<String> = fields.iter().map(|&field| name(field).to_string()).collect();
+            let rows: Vec<Vec<String>> = rows.into_iter().map(|row| row.into_iter().map(Option::unwrap_or_default).collect()).collect();
+            let widths: Vec<usize> = (0..fields.len())
+                .map(|column| rows.iter().chain([&header]).map(|row| row[column].chars().count()).max().unwrap_or(0))
+                .collect();
+            for row in iter::once(&header).chain(&rows) {
+                let line: Vec<String> = row.iter().zip(&widths).map(|(value, width)| format!("{:<1$}", value, width)).collect();
+                writeln!(out, "{}", line.join("  ").trim_end())?;
+            }
+        },
+        // tabs and line breaks within a value would split it
+        Format::Tsv => for row in rows {
+            let line: Vec<String> = row
+                .into_iter()
+                .map(|value| value.unwrap_or_default().replace('\t', "\\t").replace('\n', "\\n"))
+                .collect();
+            writeln!(out, "{}", line.join("\t"))?;
+        },
+    }
+    Ok(())
+}
+
+/// Returns one field of an event as text, or None where the event has none. Times are written in
+/// RFC 3339, or as in the interface for reading, and ids are shortened for reading.
+fn value(event: &Event, field: Field, config: &Config, human: bool) -> Option<String> {
+    let time = |time: DateTime<Local>| time.format("%FT%T%:z").to_string();
+    match field {
+        Field::Description => Some(event.description.clone()),
+        Field::End if human => end_time(event, config),
+        Field::End => match event.interval {
+            Interval::RepDefinite { end, .. } | Interval::RepIndefinite(end) | Interval::Standard(end) => Some(time(end)),
+            Interval::None => None,
+        },
+        Field::Id if human => Some(event.id.to_string()[..8].to_string()),
+        Field::Id => Some(event.id.to_string()),
+        Field::Iso => event_to_record(event).split('\t').next().filter(|iso| !iso.is_empty()).map(str::to_string),
+        Field::Kind => Some(Kind::of(event).to_string()),
+        Field::Start if human => start_time(event, config),
+        Field::Start => event.start.map(time),
+        Field::Zone => match event.zone {
+            Zone::Floating => None,
+            zone => Some(zone.to_string()),
+        },
+    }
+}
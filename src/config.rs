@@ -1,18 +1,444 @@
-/// Contains constants that the user might want to modify.
-
-/// The prompt that appears at the beginning of the insert box when in insert mode.
-pub const ADD_PROMPT: &str = "Add: >";
-/// Keep ADD_PROMPT_LEN up to date to ensure proper cursor positioning in insert mode.
-pub const ADD_PROMPT_LEN: u16 = 6;
-/// Column width for the "Time" columns of relevant panes. You may want to adjust this according
-/// to the format used for printing times in the interface.
-pub const COL_TIME_WIDTH: u16 = 12;
-/// File into which to backup events during every session. Point the constant to an empty &str to
-/// disable backups.
-pub const FILEPATH_BACKUP: &str = "/home/ty/code/clamendar/events.json.bak";
-/// File to use for de/serialization. Must be an absolute path, I think.
-pub const FILEPATH: &str = "/home/ty/code/clamendar/events.json";
-/// Preferred output Year, Month, Day format for printing only the date.
-pub const YMD: &str = "%m-%d";
-/// Preferred output Year, Month, Day, Hour, Minute format for printing the date and time.
-pub const YMDHM: &str = "%m-%d %R";
+//! Contains the settings that the user might want to modify.
+//!
+//! Settings are read at startup from `$XDG_CONFIG_HOME/clamendar/config.toml`, if it exists, for
+//! example:
+//!
+//! ```toml
+//! events_file = "/home/me/calendar/events.json"
+//! backup_dir = ""                   # disables backups
+//! backup_sessions = 10
+//! backup_days = 7
+//! storage = "json"                  # or "sqlite", if built with the sqlite feature
+//! date_format = "%m-%d"
+//! datetime_format = "%m-%d %R"
+//! col_time_width = 12
+//! add_prompt = "Add: >"
+//! ```
+//!
+//! The environment variables `CLAMENDAR_CONFIG`, `CLAMENDAR_EVENTS` and `CLAMENDAR_BACKUPS` take
+//! precedence over the file, and the command-line options `--config`, `--events` and `--backups`
+//! take precedence over those.
+
+use chrono::format::{Item, StrftimeItems};
+use clamendar::filter::Filter;
+#[cfg(feature = "sqlite")]
+use clamendar::storage::sqlite::Sqlite;
+use clamendar::storage::{json::JsonFile, Storage};
+use crate::error::Error;
+use serde::Deserialize;
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    io,
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
+
+/// How long the interface waits for input before redrawing on its own, which is also how often
+/// repeating events are checked for having passed and changes to the events file are picked up.
+pub const TICK: Duration = Duration::from_secs(1);
+
+const USAGE: &str = "\
+usage: clamendar [options] [command]
+
+commands:
+    add <record>           add the event described by <record>, as in \"2021-01-01\\thistory paper\"
+    agenda                 print what happens from today on, day by day
+    backups                list backups, numbered from the most recent
+    delete <id>            delete the event with the id <id>
+    edit <id> [record]     replace the event with [record], or print the record to change
+    export [file]          write the events to [file] as iCalendar, or to standard output
+    import <file>          add the events in the iCalendar file <file>
+    list                   list the events along with their ids
+    restore <number>       replace the events with those in a backup
+    search <text>          list the events whose description contains <text>
+
+    The interface opens without a command. Ids may be shortened to any unambiguous beginning.
+
+options:
+    -c, --config <file>    read settings from <file>
+    -e, --events <file>    read and write events in <file>
+    -b, --backups <dir>    keep backups in <dir>; \"\" disables backups
+    -d, --days <number>    print the agenda for <number> days, 7 by default
+    --filter <expression>  list only the events matching <expression>, as in
+                           'after:today before:+1w kind:repeating tag:work -text:\"draft\"'
+    -p, --pane <pane>      list only the events in <pane>: intervals, timed or untimed
+    -f, --format <format>  list events as json, jsonl, tsv (the default) or table
+    --fields <fields>      list only these fields, separated by commas, out of id, iso,
+                           description, start, end, kind and zone
+    -h, --help             print this message";
+
+/// How the events are stored.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Json,
+    Sqlite,
+}
+
+/// What to do instead of opening the interface, if anything.
+pub enum Command {
+    Add(String),
+    /// Prints the agenda for this many days, of the events matching the filter.
+    Agenda(u32, Option<Filter>),
+    Delete(String),
+    Edit(String, Option<String>),
+    Export(Option<PathBuf>),
+    Import(PathBuf),
+    Interface,
+    List(Listing),
+    ListBackups,
+    Restore(usize),
+}
+
+/// A field of an event that can be listed.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Field {
+    Description,
+    End,
+    Id,
+    /// The date and time part of the record, before the tab.
+    Iso,
+    Kind,
+    Start,
+    Zone,
+}
+
+/// A format in which events can be listed.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    /// One JSON array of objects.
+    Json,
+    /// One JSON object per line.
+    JsonLines,
+    /// Aligned columns under a header, for reading.
+    Table,
+    /// Tab-separated values, one event per line.
+    Tsv,
+}
+
+/// Which events to list on the command line, and how.
+pub struct Listing {
+    /// The fields to list, in order, or None for those suiting the format.
+    pub fields: Option<Vec<Field>>,
+    pub filter: Option<Filter>,
+    pub format: Format,
+    pub pane: Option<Pane>,
+    /// Only events whose description contains this text, ignoring case, are listed.
+    pub text: Option<String>,
+}
+
+/// One of the panes of the interface, by which listed events can be narrowed down.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pane {
+    Intervals,
+    Timed,
+    Untimed,
+}
+
+/// Settings loaded at runtime.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The prompt that appears at the beginning of the insert box when in insert mode.
+    pub add_prompt: String,
+    /// How many daily snapshots of the events to keep.
+    pub backup_days: usize,
+    /// Directory in which to keep backups of the events. None disables backups.
+    #[serde(deserialize_with = "empty_as_none")]
+    pub backup_dir: Option<PathBuf>,
+    /// How many backups of the events to keep from the start of each session.
+    pub backup_sessions: usize,
+    /// Column width for the "Time" columns of relevant panes. You may want to adjust this
+    /// according to the format used for printing times in the interface.
+    pub col_time_width: u16,
+    /// Preferred output format for printing only the date.
+    pub date_format: String,
+    /// Preferred output format for printing the date and time.
+    pub datetime_format: String,
+    /// File to use for de/serialization.
+    pub events_file: PathBuf,
+    /// Whether the events file is a JSON file or an SQLite database.
+    pub storage: Backend,
+}
+
+impl Config {
+    /// Loads the settings from the config file, the environment and the command line, in
+    /// increasing order of precedence, then checks them. Also returns the command given on the
+    /// command line. Prints usage and exits if asked to.
+    pub fn load() -> Result<(Self, Command), Error> {
+        let mut args = env::args_os().skip(1).peekable();
+        let (mut config_file, mut events_file, mut backup_dir) = (None, None, None);
+        let (mut days, mut filter, mut pane, mut format, mut fields) = (None, None, None, None, None);
+        let mut command = Command::Interface;
+        while let Some(arg) = args.next() {
+            let slot = match arg.to_str() {
+                Some("-c") | Some("--config") => &mut config_file,
+                Some("-e") | Some("--events") => &mut events_file,
+                Some("-b") | Some("--backups") => &mut backup_dir,
+                Some("-d") | Some("--days") => &mut days,
+                Some("--filter") => &mut filter,
+                Some("-p") | Some("--pane") => &mut pane,
+                Some("-f") | Some("--format") => &mut format,
+                Some("--fields") => &mut fields,
+                Some("-h") | Some("--help") => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                },
+                Some("add") => {
+                    command = Command::Add(record(args.next(), "add")?);
+                    continue;
+                },
+                Some("agenda") => {
+                    command = Command::Agenda(7, None);
+                    continue;
+                },
+                Some("backups") => {
+                    command = Command::ListBackups;
+                    continue;
+                },
+                Some("delete") => {
+                    command = Command::Delete(string(args.next(), "delete requires the id of an event")?);
+                    continue;
+                },
+                Some("edit") => {
+                    let id = string(args.next(), "edit requires the id of an event")?;
+                    // a record may start with a dash, as in "-1d\tyesterday", but then has a tab
+                    let arg = args.next_if(|arg| {
+                        let arg = arg.to_string_lossy();
+                        !arg.starts_with('-') || arg.contains('\t') || arg.contains("\\t")
+                    });
+                    let record = match arg {
+                        Some(arg) => Some(record(Some(arg), "edit")?),
+                        None => None,
+                    };
+                    command = Command::Edit(id, record);
+                    continue;
+                },
+                Some("export") => {
+                    // the file is optional, so an option following the command is not taken for it
+                    command = Command::Export(args.next_if(|arg| !arg.to_string_lossy().starts_with('-')).map(PathBuf::from));
+                    continue;
+                },
+                Some("import") => {
+                    match args.next() {
+                        Some(path) => command = Command::Import(PathBuf::from(path)),
+                        None => return Err(Error::Config("import requires a file".to_string())),
+                    }
+                    continue;
+                },
+                Some("list") => {
+                    command = Command::List(Listing { fields: None, filter: None, format: Format::Tsv, pane: None, text: None });
+                    continue;
+                },
+                Some("restore") => {
+                    let number = args.next().and_then(|number| number.to_str()?.parse().ok());
+                    match number {
+                        Some(number) => command = Command::Restore(number),
+                        None => return Err(Error::Config("restore requires the number of a backup".to_string())),
+                    }
+                    continue;
+                },
+                Some("search") => {
+                    let text = string(args.next(), "search requires the text to search for")?;
+                    command = Command::List(Listing { fields: None, filter: None, format: Format::Tsv, pane: None, text: Some(text) });
+                    continue;
+                },
+                _ => return Err(Error::Config(format!("unexpected argument: {}", arg.to_string_lossy()))),
+            };
+            match args.next() {
+                Some(value) => *slot = Some(value),
+                None => return Err(Error::Config(format!("{} requires a value", arg.to_string_lossy()))),
+            }
+        }
+        if let Some(number) = days {
+            match &mut command {
+                Command::Agenda(days, _) => match number.to_str().and_then(|number| number.parse().ok()) {
+                    Some(number) if number > 0 => *days = number,
+                    _ => return Err(Error::Config(format!("--days requires a positive number: {}", number.to_string_lossy()))),
+                },
+                _ => return Err(Error::Config("--days only applies to agenda".to_string())),
+            }
+        }
+        if let Some(expression) = filter {
+            let filter = expression.to_string_lossy().parse()?;
+            match &mut command {
+                Command::Agenda(_, slot) => *slot = Some(filter),
+                Command::List(listing) => listing.filter = Some(filter),
+                _ => return Err(Error::Config("--filter only applies to agenda, list and search".to_string())),
+            }
+        }
+        if let Command::List(listing) = &mut command {
+            if let Some(name) = pane {
+                listing.pane = Some(match name.to_str() {
+                    Some("intervals") => Pane::Intervals,
+                    Some("timed") => Pane::Timed,
+                    Some("untimed") => Pane::Untimed,
+                    _ => return Err(Error::Config(format!("no such pane: {}", name.to_string_lossy()))),
+                });
+            }
+            if let Some(name) = format {
+                listing.format = match name.to_str() {
+                    Some("json") => Format::Json,
+                    Some("jsonl") => Format::JsonLines,
+                    Some("table") => Format::Table,
+                    Some("tsv") => Format::Tsv,
+                    _ => return Err(Error::Config(format!("no such format: {}", name.to_string_lossy()))),
+                };
+            }
+            if let Some(names) = fields {
+                let names = names.to_string_lossy().into_owned();
+                let fields = names
+                    .split(',')
+                    .map(|name| match name.trim() {
+                        "description" => Ok(Field::Description),
+                        "end" => Ok(Field::End),
+                        "id" => Ok(Field::Id),
+                        "iso" => Ok(Field::Iso),
+                        "kind" => Ok(Field::Kind),
+                        "start" => Ok(Field::Start),
+                        "zone" => Ok(Field::Zone),
+                        name => Err(Error::Config(format!("no such field: {}", name))),
+                    })
+                    .collect::<Result<_, _>>()?;
+                listing.fields = Some(fields);
+            }
+        } else if pane.is_some() || format.is_some() || fields.is_some() {
+            return Err(Error::Config("--pane, --format and --fields only apply to list and search".to_string()));
+        }
+
+        // a config file named explicitly must exist; the default one need not
+        let mut config = match config_file.or_else(|| env::var_os("CLAMENDAR_CONFIG")) {
+            Some(path) => Config::from_file(Path::new(&path))?
+                .ok_or_else(|| Error::Config(format!("{}: no such file", Path::new(&path).display())))?,
+            None => match config_home() {
+                Some(home) => Config::from_file(&home.join("clamendar/config.toml"))?.unwrap_or_default(),
+                None => Config::default(),
+            },
+        };
+        match events_file.or_else(|| env::var_os("CLAMENDAR_EVENTS")) {
+            Some(path) => config.events_file = PathBuf::from(path),
+            // a database left at the default location is not named as if it were JSON
+            None if config.storage == Backend::Sqlite && config.events_file == Config::default().events_file => {
+                config.events_file.set_extension("db");
+            },
+            None => {},
+        }
+        if let Some(path) = backup_dir.or_else(|| env::var_os("CLAMENDAR_BACKUPS")) {
+            config.backup_dir = if path.is_empty() { None } else { Some(PathBuf::from(path)) };
+        }
+        config.validate()?;
+        Ok((config, command))
+    }
+
+    /// The width of the prompt in columns, for positioning the cursor in insert mode.
+    pub fn add_prompt_len(&self) -> u16 {
+        self.add_prompt.chars().count().min(u16::MAX as usize) as u16
+    }
+
+    /// Opens the storage of the events, creating the directory it is kept in if needed.
+    pub fn storage(&self) -> Result<Box<dyn Storage>, Error> {
+        if let Some(parent) = self.events_file.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(match self.storage {
+            Backend::Json => Box::new(JsonFile::new(&self.events_file)),
+            #[cfg(feature = "sqlite")]
+            Backend::Sqlite => Box::new(Sqlite::open(&self.events_file)?),
+            #[cfg(not(feature = "sqlite"))]
+            Backend::Sqlite => unreachable!("rejected by validate"),
+        })
+    }
+
+    /// Reads settings from a TOML file, or returns None if there is no such file.
+    fn from_file(path: &Path) -> Result<Option<Self>, Error> {
+        match fs::read_to_string(path) {
+            Ok(file) => toml::from_str(&file)
+                .map(Some)
+                .map_err(|error| Error::Config(format!("{}: {}", path.display(), error))),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(Error::Config(format!("{}: {}", path.display(), error))),
+        }
+    }
+
+    /// Rejects settings that would only fail later on, such as malformed time formats.
+    fn validate(&self) -> Result<(), Error> {
+        if self.events_file.as_os_str().is_empty() {
+            return Err(Error::Config("events_file must not be empty".to_string()));
+        }
+        if self.backup_sessions == 0 {
+            return Err(Error::Config("backup_sessions must be at least 1; set backup_dir = \"\" to disable backups".to_string()));
+        }
+        if cfg!(not(feature = "sqlite")) && self.storage == Backend::Sqlite {
+            return Err(Error::Config("storage = \"sqlite\" requires clamendar to be built with the sqlite feature".to_string()));
+        }
+        if self.col_time_width == 0 {
+            return Err(Error::Config("col_time_width must be at least 1".to_string()));
+        }
+        for (name, format) in [("date_format", &self.date_format), ("datetime_format", &self.datetime_format)] {
+            if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                return Err(Error::Config(format!("{} is not a valid time format: {}", name, format)));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let data = data_home().unwrap_or_default().join("clamendar");
+        Config {
+            add_prompt: "Add: >".to_string(),
+            backup_days: 7,
+            backup_dir: Some(data.join("backups")),
+            backup_sessions: 10,
+            col_time_width: 12,
+            date_format: "%m-%d".to_string(),
+            datetime_format: "%m-%d %R".to_string(),
+            events_file: data.join("events.json"),
+            storage: Backend::Json,
+        }
+    }
+}
+
+/// Returns `$XDG_CONFIG_HOME`, falling back to `~/.config`.
+fn config_home() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// Returns `$XDG_DATA_HOME`, falling back to `~/.local/share`.
+fn data_home() -> Option<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+/// Takes an event as entered in the interface from the command line, where a literal `\t` stands
+/// for the tab, since a tab is awkward to type in a shell.
+fn record(arg: Option<OsString>, command: &str) -> Result<String, Error> {
+    let record = string(arg, &format!("{} requires an event, as in \"2021-01-01\\thistory paper\"", command))?;
+    Ok(if record.contains('\t') { record } else { record.replacen("\\t", "\t", 1) })
+}
+
+/// Takes a required argument as a string, or reports it missing with `message`.
+fn string(arg: Option<OsString>, message: &str) -> Result<String, Error> {
+    match arg {
+        Some(arg) => arg.into_string().map_err(|arg| Error::Config(format!("not valid unicode: {}", arg.to_string_lossy()))),
+        None => Err(Error::Config(message.to_string())),
+    }
+}
+
+/// Treats an empty path in the config file as leaving the setting off.
+fn empty_as_none<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<PathBuf>, D::Error> {
+    let path = PathBuf::deserialize(deserializer)?;
+    Ok(if path.as_os_str().is_empty() { None } else { Some(path) })
+}
+
+/// Reads an XDG base directory from the environment, which must be absolute to count, falling back
+/// to a directory under the home directory.
+fn xdg_dir(variable: &str, fallback: &str) -> Option<PathBuf> {
+    env::var_os(variable)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").filter(|home| !home.is_empty()).map(|home| PathBuf::from(home).join(fallback)))
+}
@@ -1,21 +1,102 @@
-/// Contains the custom Error enum.
+//! Contains the custom Error enum.
 
-use crossterm;
-use serde_json;
-use std::io;
+use clamendar::{filter::ParseFilterError, iso, storage::StorageError, zone};
+use std::{fmt, io};
 
 /// Custom errors for the whole project.
 #[derive(Debug)]
 pub enum Error {
+    AmbiguousEvent(String),
+    Config(String),
+    Conflict,
     Crossterm(crossterm::ErrorKind),
+    DeletionScope,
     DeletionWarning,
+    FileCorrupt(serde_json::Error),
+    FileEmpty,
+    FileMissing,
+    FileVersion(u64),
+    Filter(ParseFilterError),
     InvalidIso,
     InvalidRecord,
+    InvalidRule,
     InvalidTime,
     Io(io::Error),
+    Iso(iso::ParseError),
     NoInfo,
+    NoSuchBackup(usize),
+    NoSuchEvent(String),
+    Reloaded,
+    Restored(usize),
     Serde(serde_json::Error),
+    Storage(StorageError),
+    Watch(notify::Error),
+    YankScope,
     YankWarning,
+    Zone(zone::ParseZoneError),
+}
+
+impl Error {
+    /// The status with which the program exits on this error, by which scripts can tell what went
+    /// wrong: 2 for invalid usage or settings, 3 for an event that could not be understood, 4 for
+    /// an event or backup that was not found, 5 for an events file that could not be used, and 1
+    /// for anything else.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) | Error::Filter(_) => 2,
+            Error::InvalidIso
+                | Error::InvalidRecord
+                | Error::InvalidRule
+                | Error::InvalidTime
+                | Error::Iso(_)
+                | Error::NoInfo
+                | Error::Zone(_)
+            => 3,
+            Error::AmbiguousEvent(_) | Error::NoSuchBackup(_) | Error::NoSuchEvent(_) => 4,
+            Error::Conflict
+                | Error::FileCorrupt(_)
+                | Error::FileEmpty
+                | Error::FileMissing
+                | Error::FileVersion(_)
+            => 5,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::AmbiguousEvent(id) => write!(f, "more than one event has an id starting with {}", id),
+            Error::Config(message) => write!(f, "invalid configuration: {}", message),
+            Error::Conflict => write!(f, "the events file changed on disk since it was read"),
+            Error::Crossterm(error) => write!(f, "terminal error: {}", error),
+            Error::DeletionScope => write!(f, "press 'o' or 'a' to delete this occurrence or all"),
+            Error::DeletionWarning => write!(f, "press 'd' again to delete (irreversible)"),
+            Error::FileCorrupt(error) => write!(f, "the events file is corrupt: {}", error),
+            Error::FileEmpty => write!(f, "the events file is empty"),
+            Error::FileMissing => write!(f, "the events file does not exist"),
+            Error::FileVersion(version) => write!(f, "the events file is from a newer version of clamendar (file version {})", version),
+            Error::Filter(error) => write!(f, "{}", error),
+            Error::InvalidIso => write!(f, "the string was not properly formatted"),
+            Error::InvalidRecord => write!(f, "input: \"[iso string]\\t[description]\""),
+            Error::InvalidRule => write!(f, "the recurrence rule was not understood"),
+            Error::InvalidTime => write!(f, "the time entered was invalid or not specific enough"),
+            Error::Io(error) => write!(f, "i/o error: {}", error),
+            Error::Iso(error) => write!(f, "invalid ISO 8601: {}", error),
+            Error::NoInfo => write!(f, "the event contained no information, so was not added"),
+            Error::NoSuchBackup(number) => write!(f, "there is no backup number {}; see `clamendar backups`", number),
+            Error::NoSuchEvent(id) => write!(f, "there is no event with the id {}; see `clamendar list`", id),
+            Error::Reloaded => write!(f, "reloaded the events, which changed on disk"),
+            Error::Restored(count) => write!(f, "restored {} events; the previous ones were backed up first", count),
+            Error::Serde(error) => write!(f, "could not de/serialize events: {}", error),
+            Error::Storage(error) => write!(f, "{}", error),
+            Error::Watch(error) => write!(f, "cannot watch the events file for changes: {}", error),
+            Error::YankScope => write!(f, "press 'o' or 'a' to yank this occurrence or all"),
+            Error::YankWarning => write!(f, "press 'y' again to yank selected event into buffer"),
+            Error::Zone(error) => write!(f, "{}", error),
+        }
+    }
 }
 
 impl From<crossterm::ErrorKind> for Error {
@@ -24,14 +105,52 @@ impl From<crossterm::ErrorKind> for Error {
     }
 }
 
+impl From<ParseFilterError> for Error {
+    fn from(error: ParseFilterError) -> Self {
+        Error::Filter(error)
+    }
+}
+
+impl From<iso::ParseError> for Error {
+    fn from(error: iso::ParseError) -> Self {
+        Error::Iso(error)
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Self {
         Error::Io(error)
     }
 }
 
+impl From<notify::Error> for Error {
+    fn from(error: notify::Error) -> Self {
+        Error::Watch(error)
+    }
+}
+
 impl From<serde_json::Error> for Error {
     fn from(error: serde_json::Error) -> Self {
         Error::Serde(error)
     }
 }
+
+// the kinds of failure common to every store have variants of their own
+impl From<StorageError> for Error {
+    fn from(error: StorageError) -> Self {
+        match error {
+            StorageError::Corrupt(error) => Error::FileCorrupt(error),
+            StorageError::Empty => Error::FileEmpty,
+            StorageError::Io(error) => Error::Io(error),
+            StorageError::Missing => Error::FileMissing,
+            StorageError::Version(version) => Error::FileVersion(version),
+            error => Error::Storage(error),
+        }
+    }
+}
+
+impl From<zone::ParseZoneError> for Error {
+    fn from(error: zone::ParseZoneError) -> Self {
+        Error::Zone(error)
+    }
+}
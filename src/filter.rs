@@ -0,0 +1,256 @@
+//! Contains a small language for picking out events, as in
+//! `before:2021-06-01 after:today text:"history paper" kind:repeating tag:work`.
+//!
+//! An expression is a list of terms separated by spaces, all of which an event must match. A term
+//! is a key and a value separated by a colon, and a value holding spaces may be quoted. A term
+//! without a key searches the description, and a term preceded by `-` matches the events that the
+//! term itself does not. The keys are:
+//!
+//! - `after:<date>`: the event happens at or after the date, counting every occurrence of a
+//!   repeating event and the end of an interval.
+//! - `before:<date>`: the event starts before the date.
+//! - `is:upcoming` and `is:past`: the event has yet to happen or has happened, as in
+//!   `Event::is_upcoming`. Untimed events are always upcoming.
+//! - `kind:<kind>`: the event is a `single` event, an `interval`, a `repeating` event, or
+//!   `untimed`.
+//! - `tag:<tag>`: the description holds `#<tag>` as a word of its own.
+//! - `text:<text>`: the description, or that of a replaced occurrence, contains the text.
+//!
+//! Dates are ISO 8601 or relative expressions, as in the insert format, and are resolved when the
+//! expression is parsed. A date without a time is the start of that day. Text and tags are
+//! matched ignoring case.
+
+use chrono::{DateTime, Local, TimeZone};
+use crate::{iso, relative, Event, Interval};
+use std::{fmt, mem, str::FromStr};
+
+/// A parsed filter expression.
+#[derive(Debug)]
+pub struct Filter {
+    /// Each term, and whether it is negated.
+    terms: Vec<(bool, Term)>,
+}
+
+/// What sort of event an event is, by the pane it appears in and whether it repeats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Interval,
+    Repeating,
+    Single,
+    Untimed,
+}
+
+/// Returned when a filter expression cannot be understood, describing the offending term.
+#[derive(Debug, PartialEq)]
+pub struct ParseFilterError(pub String);
+
+#[derive(Debug)]
+enum Term {
+    After(DateTime<Local>),
+    Before(DateTime<Local>),
+    Kind(Kind),
+    Tag(String),
+    Text(String),
+    Upcoming(bool),
+}
+
+impl Filter {
+    /// Returns true if the event matches every term.
+    pub fn matches(&self, event: &Event) -> bool {
+        self.terms.iter().all(|(negated, term)| term.matches(event) != *negated)
+    }
+}
+
+impl Kind {
+    pub fn of(event: &Event) -> Self {
+        match (event.start, &event.interval) {
+            (None, _) => Kind::Untimed,
+            (Some(_), Interval::None) => Kind::Single,
+            (Some(_), Interval::Standard(_)) => Kind::Interval,
+            (Some(_), _) => Kind::Repeating,
+        }
+    }
+}
+
+impl Term {
+    fn matches(&self, event: &Event) -> bool {
+        match self {
+            Term::After(date) => event.occurrences(*date, Local.ymd(9999, 1, 1).and_hms(0, 0, 0)).next().is_some(),
+            Term::Before(date) => event.start.is_some_and(|start| event.occurrences(start, *date).next().is_some()),
+            Term::Kind(kind) => Kind::of(event) == *kind,
+            Term::Tag(tag) => event.description
+                .split(|c: char| c.is_whitespace() || (c.is_ascii_punctuation() && c != '#' && c != '-' && c != '_'))
+                .any(|word| word.strip_prefix('#').is_some_and(|word| word.to_lowercase() == *tag)),
+            Term::Text(text) => event.description.to_lowercase().contains(text)
+                || event.overrides.iter().any(|replacement| replacement.description.to_lowercase().contains(text)),
+            Term::Upcoming(upcoming) => event.is_upcoming() == *upcoming,
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = ParseFilterError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let mut terms = Vec::new();
+        for (negated, key, value) in tokenize(string)? {
+            let invalid = || ParseFilterError(format!("{}:{}", key.as_deref().unwrap_or(""), value));
+            let term = match key.as_deref() {
+                Some("after") => Term::After(date(&value).ok_or_else(invalid)?),
+                Some("before") => Term::Before(date(&value).ok_or_else(invalid)?),
+                Some("is") => match value.as_str() {
+                    "upcoming" => Term::Upcoming(true),
+                    "past" => Term::Upcoming(false),
+                    _ => return Err(invalid()),
+                },
+                Some("kind") => Term::Kind(value.parse().map_err(|_| invalid())?),
+                Some("tag") => Term::Tag(value.trim_start_matches('#').to_lowercase()),
+                Some("text") | None => Term::Text(value.to_lowercase()),
+                Some(_) => return Err(invalid()),
+            };
+            terms.push((negated, term));
+        }
+        Ok(Filter { terms })
+    }
+}
+
+impl FromStr for Kind {
+    type Err = ParseFilterError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "interval" => Ok(Kind::Interval),
+            "repeating" => Ok(Kind::Repeating),
+            "single" => Ok(Kind::Single),
+            "untimed" => Ok(Kind::Untimed),
+            _ => Err(ParseFilterError(string.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::Interval => write!(f, "interval"),
+            Kind::Repeating => write!(f, "repeating"),
+            Kind::Single => write!(f, "single"),
+            Kind::Untimed => write!(f, "untimed"),
+        }
+    }
+}
+
+impl fmt::Display for ParseFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid filter term: {}", self.0)
+    }
+}
+
+/// Resolves a date as ISO 8601, or failing that as a relative expression.
+fn date(value: &str) -> Option<DateTime<Local>> {
+    match iso::parse(value) {
+        Ok(parsed) => parsed.in_zone(&Local),
+        Err(_) => relative::parse(value, Local::now()),
+    }
+}
+
+/// Splits an expression into its terms, each as whether it is negated, its key, if any, and its
+/// value with any quotes removed. Only a colon after a key of letters alone, outside quotes, ends
+/// the key, so that values such as `10:00` are left whole.
+fn tokenize(string: &str) -> Result<Vec<(bool, Option<String>, String)>, ParseFilterError> {
+    let mut tokens = Vec::new();
+    let (mut negated, mut key, mut value) = (false, None, String::new());
+    // a pair of quotes makes a term even with nothing between them
+    let (mut quoted, mut started) = (false, false);
+    for c in string.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                started = true;
+            },
+            c if c.is_whitespace() && !quoted => if started {
+                tokens.push((mem::take(&mut negated), key.take(), mem::take(&mut value)));
+                started = false;
+            },
+            ':' if !quoted && key.is_none() && !value.is_empty() && value.chars().all(|c: char| c.is_ascii_alphabetic()) => {
+                key = Some(mem::take(&mut value).to_lowercase());
+            },
+            '-' if !started => {
+                negated = true;
+                started = true;
+            },
+            c => {
+                value.push(c);
+                started = true;
+            },
+        }
+    }
+    if quoted { return Err(ParseFilterError(format!("unterminated quote in {}", string))); }
+    if started { tokens.push((negated, key, value)); }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use super::*;
+
+    fn event(description: &str, start: Option<DateTime<Local>>) -> Event {
+        Event { start, ..Event::new(description.to_string()) }
+    }
+
+    fn matches(expression: &str, event: &Event) -> bool {
+        expression.parse::<Filter>().unwrap().matches(event)
+    }
+
+    #[test]
+    fn quoting() {
+        let tokens = |string| tokenize(string).unwrap();
+        assert_eq!(tokens(r#"text:"history paper" due"#), [
+            (false, Some("text".to_string()), "history paper".to_string()),
+            (false, None, "due".to_string()),
+        ]);
+        // a colon in quotes, or after something other than letters, does not end a key
+        assert_eq!(tokens(r#""a:b" 10:00"#), [(false, None, "a:b".to_string()), (false, None, "10:00".to_string())]);
+        assert_eq!(tokens(r#"text:"""#), [(false, Some("text".to_string()), String::new())]);
+        assert!(tokenize(r#"text:"history paper"#).is_err());
+        let paper = event("History paper", None);
+        assert!(matches(r#""history paper""#, &paper));
+        assert!(!matches("history paper due", &paper));
+    }
+
+    #[test]
+    fn negation() {
+        assert_eq!(tokenize("-tag:work -\"a b\" a-b").unwrap(), [
+            (true, Some("tag".to_string()), "work".to_string()),
+            (true, None, "a b".to_string()),
+            (false, None, "a-b".to_string()),
+        ]);
+        let (work, home) = (event("standup #work", None), event("dishes #home", None));
+        assert!(!matches("-tag:work", &work));
+        assert!(matches("-tag:work", &home));
+        assert!(matches("-kind:single", &work));
+        assert!(!matches("-kind:untimed", &work));
+    }
+
+    #[test]
+    fn precedence() {
+        // every term must match, each negation applying to its own term alone
+        let tomorrow = Local::now() + Duration::days(1);
+        let paper = event("history paper #school", Some(tomorrow));
+        assert!(matches("tag:school is:upcoming -text:essay", &paper));
+        assert!(!matches("tag:school -is:upcoming", &paper));
+        assert!(!matches("-tag:school is:upcoming", &paper));
+        assert!(matches("after:today before:+2d kind:single", &paper));
+        assert!(!matches("after:today before:+2d -kind:single", &paper));
+        assert!(matches("", &paper));
+    }
+
+    #[test]
+    fn invalid_terms() {
+        let error = |expression: &str| expression.parse::<Filter>().unwrap_err();
+        assert_eq!(error("when:today"), ParseFilterError("when:today".to_string()));
+        assert_eq!(error("is:soon"), ParseFilterError("is:soon".to_string()));
+        assert_eq!(error("kind:weekly"), ParseFilterError("kind:weekly".to_string()));
+        assert_eq!(error("before:someday"), ParseFilterError("before:someday".to_string()));
+    }
+}
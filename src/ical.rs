@@ -0,0 +1,593 @@
+//! Contains the conversion of events to and from iCalendar (RFC 5545), for use in and from other
+//! calendar clients.
+//!
+//! Single events become a VEVENT with only a DTSTART, intervals one with a DTSTART and a DTEND,
+//! and repeating events one with an RRULE, counted for definite ones and open-ended otherwise.
+//! Skipped occurrences become EXDATEs, and replaced occurrences separate VEVENTs naming the one
+//! they replace in a RECURRENCE-ID. Untimed events become VTODOs.
+//!
+//! Floating events are written in floating time, and those whose times all fall on midnight as
+//! dates, the way they are shown in the interface. Events at a fixed offset are written in UTC.
+//! Events in a named timezone are written with its IANA name as their TZID; no VTIMEZONE is
+//! included, which most clients accept, since they know the IANA names themselves. The id of an
+//! event is its UID.
+//!
+//! Importing does the reverse, and also takes all-day events lasting a single day as single events
+//! and to-dos with a DUE as single events at that time. Rules ending at an UNTIL are counted
+//! instead, and those repeating more often than daily repeat by a fixed period. What cannot be
+//! represented, such as the length of a repeating event or a LOCATION, is dropped and reported.
+//! TZIDs are taken to be IANA names, possibly behind a prefix like `/mozilla.org/20050126_1/`.
+//! A UID becomes the id of its event if it is a UUID, as those written by clamendar are, and is
+//! otherwise turned into one, always the same for the same UID.
+
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use crate::{iso, recurrence::Recurrence, zone::Zone, Event, Interval, Override};
+use std::collections::{BTreeMap, HashSet};
+use uuid::Uuid;
+
+/// Properties that only describe the file or the component itself, which are not worth reporting
+/// when dropped.
+const METADATA: [&str; 7] = ["CLASS", "CREATED", "DTSTAMP", "LAST-MODIFIED", "SEQUENCE", "TRANSP", "UID"];
+
+/// Lines longer than this many octets are folded.
+const LINE_LENGTH: usize = 75;
+
+/// The namespace in which UIDs that are not UUIDs are turned into ids.
+const NAMESPACE: Uuid = Uuid::from_u128(0x6c0c_3c5e_8f1d_4b7a_9e2d_52a1_c4f0_7b39);
+
+/// The outcome of reading an iCalendar file.
+pub struct Import {
+    /// Events that were not already present.
+    pub events: Vec<Event>,
+    /// How many events were left out for being already present, by their UID.
+    pub duplicates: usize,
+    /// Descriptions of what could not be represented, one per line.
+    pub problems: Vec<String>,
+}
+
+/// A property of a component, as in `DTSTART;TZID=Europe/Paris:20210101T090000`.
+struct Property {
+    name: String,
+    parameters: Vec<(String, String)>,
+    value: String,
+}
+
+/// A VEVENT or VTODO, and the line it starts on.
+struct Component {
+    kind: String,
+    line: usize,
+    properties: Vec<Property>,
+}
+
+/// Writes the events as an iCalendar file.
+pub fn export(events: &[&Event]) -> String {
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:-//clamendar//clamendar {}//EN", env!("CARGO_PKG_VERSION")),
+        "CALSCALE:GREGORIAN".to_string(),
+    ];
+    for event in events {
+        component(event, &stamp, &mut lines);
+    }
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+/// Writes the component, or components, describing one event.
+fn component(event: &Event, stamp: &str, lines: &mut Vec<String>) {
+    let uid = format!("UID:{}", event.id);
+    let summary = format!("SUMMARY:{}", escape(&event.description));
+    let start = match event.start {
+        Some(start) => start,
+        None => {
+            lines.extend(["BEGIN:VTODO".to_string(), uid, format!("DTSTAMP:{}", stamp), summary, "END:VTODO".to_string()]);
+            return;
+        },
+    };
+    let dates = in_dates(event);
+    lines.extend(["BEGIN:VEVENT".to_string(), uid.clone(), format!("DTSTAMP:{}", stamp), time("DTSTART", start, event.zone, dates)]);
+    match &event.interval {
+        Interval::None => {},
+        Interval::Standard(end) => lines.push(time("DTEND", *end, event.zone, dates)),
+        Interval::RepDefinite { occurrences, end } => {
+            lines.push(format!("RRULE:{};COUNT={}", rule(event, start, *end), occurrences));
+        },
+        Interval::RepIndefinite(end) => lines.push(format!("RRULE:{}", rule(event, start, *end))),
+    }
+    for exception in &event.exceptions {
+        lines.push(time("EXDATE", *exception, event.zone, dates));
+    }
+    lines.extend([summary, "END:VEVENT".to_string()]);
+    for replacement in &event.overrides {
+        lines.extend([
+            "BEGIN:VEVENT".to_string(),
+            uid.clone(),
+            format!("DTSTAMP:{}", stamp),
+            time("RECURRENCE-ID", replacement.occurrence, event.zone, dates),
+            time("DTSTART", replacement.start, event.zone, dates),
+            format!("SUMMARY:{}", escape(&replacement.description)),
+            "END:VEVENT".to_string(),
+        ]);
+    }
+}
+
+/// Escapes the characters that are special in TEXT values.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Builds an event from a VEVENT or VTODO, reporting what is dropped. Returns None, having said
+/// why, if there is no sensible event to make of it.
+fn event_from(component: &Component, problems: &mut Vec<String>, dropped: &mut BTreeMap<String, usize>) -> Option<Event> {
+    let problem = |problems: &mut Vec<String>, message: String| {
+        problems.push(format!("line {}: {}: {}", component.line, summary_of(component), message));
+    };
+    let mut event = Event::new(summary_of(component));
+    let mut end = None;
+    let mut duration = None;
+    let mut rule = None;
+    let mut exceptions = Vec::new();
+    for property in &component.properties {
+        match (component.kind.as_str(), property.name.as_str()) {
+            (_, "SUMMARY") => {},
+            (_, "UID") => event.id = id_of(&property.value),
+            ("VEVENT", "DTSTART") | ("VTODO", "DUE") => match time_of(property) {
+                Ok((start, zone)) => {
+                    event.start = Some(start);
+                    event.zone = zone;
+                },
+                Err(message) => {
+                    problem(problems, message);
+                    return None;
+                },
+            },
+            ("VEVENT", "DTEND") => match time_of(property) {
+                Ok((time, _)) => end = Some(time),
+                Err(message) => problem(problems, message),
+            },
+            ("VEVENT", "DURATION") => match iso::parse_duration(&property.value) {
+                Ok(parsed) => duration = Some(parsed),
+                Err(_) => problem(problems, format!("DURATION {} was not understood", property.value)),
+            },
+            ("VEVENT", "RRULE") => rule = Some(property.value.clone()),
+            ("VEVENT", "EXDATE") => for value in property.value.split(',') {
+                match time_of(&Property { name: property.name.clone(), parameters: property.parameters.clone(), value: value.to_string() }) {
+                    Ok((time, _)) => exceptions.push(time),
+                    Err(message) => problem(problems, message),
+                }
+            },
+            (_, name) if !METADATA.contains(&name) && !name.starts_with("X-") => {
+                *dropped.entry(name.to_string()).or_default() += 1;
+            },
+            _ => {},
+        }
+    }
+    let start = match event.start {
+        Some(start) => start,
+        None => return Some(event),
+    };
+    let zone = event.zone;
+    if let (None, Some(duration)) = (end, duration) {
+        end = duration.add_to(start.with_timezone(&zone)).map(|end| end.with_timezone(&Local));
+    }
+    match rule {
+        Some(rule) => {
+            if end.is_some_and(|end| end != start) {
+                problem(problems, "the length of each occurrence was dropped, as repeating events have none".to_string());
+            }
+            match repeat(&mut event, &rule) {
+                Ok(()) => event.exceptions = exceptions,
+                Err(message) => problem(problems, format!("{}; only the first occurrence was imported", message)),
+            }
+        },
+        // an all-day event lasting a single day is shown just like a single event on that day
+        None => match end {
+            Some(end) if end > start && !(event.zone.is_floating() && end == start + Duration::days(1) && start.num_seconds_from_midnight() == 0) => {
+                event.interval = Interval::Standard(end);
+            },
+            _ => {},
+        },
+    }
+    Some(event)
+}
+
+/// Ends a content line, splitting it into lines of at most LINE_LENGTH octets, each continued on
+/// the next after a space. Characters are never split.
+fn fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > LINE_LENGTH {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+/// Returns the id of the event with the given UID.
+fn id_of(uid: &str) -> Uuid {
+    Uuid::parse_str(uid).unwrap_or_else(|_| Uuid::new_v5(&NAMESPACE, uid.as_bytes()))
+}
+
+/// Reads events from an iCalendar file, leaving out those whose UID is that of one of the
+/// `existing` events, or of an event earlier in the file.
+pub fn import(calendar: &str, existing: &[&Event]) -> Import {
+    let mut known: HashSet<Uuid> = existing.iter().map(|event| event.id).collect();
+    let mut import = Import { events: Vec::new(), duplicates: 0, problems: Vec::new() };
+    let mut dropped = BTreeMap::new();
+    let (components, skipped) = parse(calendar);
+    for (kind, line) in skipped {
+        import.problems.push(format!("line {}: {} components are not supported", line, kind));
+    }
+    // replaced occurrences refer to their event by UID, so they are gathered once all are read
+    let mut replacements = Vec::new();
+    for component in &components {
+        if component.properties.iter().any(|property| property.name == "RECURRENCE-ID") {
+            replacements.push(component);
+            continue;
+        }
+        if let Some(event) = event_from(component, &mut import.problems, &mut dropped) {
+            import.events.push(event);
+        }
+    }
+    for component in replacements {
+        let id = component.properties.iter().find(|property| property.name == "UID").map(|property| id_of(&property.value));
+        let series = import.events.iter_mut().find(|event| Some(event.id) == id);
+        let times = |name: &str| component.properties.iter().find(|property| property.name == name).map(time_of);
+        match (series, times("RECURRENCE-ID"), times("DTSTART")) {
+            (Some(series), Some(Ok((occurrence, _))), Some(Ok((start, _)))) => series.overrides.push(Override {
+                occurrence,
+                start,
+                description: summary_of(component),
+            }),
+            _ => import.problems.push(format!(
+                "line {}: {}: a replaced occurrence of an event that was not found or not understood was dropped",
+                component.line,
+                summary_of(component),
+            )),
+        }
+    }
+    for (name, count) in dropped {
+        import.problems.push(format!("{} was dropped from {} component{}", name, count, if count == 1 { "" } else { "s" }));
+    }
+    let events = std::mem::take(&mut import.events);
+    for event in events {
+        if known.insert(event.id) { import.events.push(event); } else { import.duplicates += 1; }
+    }
+    import
+}
+
+/// Returns true if the event is written in dates rather than times, which is the case for
+/// floating events whose every time falls on midnight.
+fn in_dates(event: &Event) -> bool {
+    let end = match event.interval {
+        Interval::RepDefinite { end, .. } | Interval::RepIndefinite(end) | Interval::Standard(end) => Some(end),
+        Interval::None => None,
+    };
+    let overrides = event.overrides.iter().flat_map(|replacement| [replacement.occurrence, replacement.start]);
+    event.zone.is_floating() && event.start
+        .into_iter()
+        .chain(end)
+        .chain(event.exceptions.iter().copied())
+        .chain(overrides)
+        .all(|time| time.num_seconds_from_midnight() == 0)
+}
+
+/// Splits an iCalendar file into the VEVENT and VTODO components it holds, after unfolding its
+/// lines. Also returns the other kinds of component that hold events of their own, which are not
+/// supported, along with the lines they start on.
+fn parse(calendar: &str) -> (Vec<Component>, Vec<(String, usize)>) {
+    // a line starting with whitespace continues the one before it
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (number, line) in calendar.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        match (line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')), lines.last_mut()) {
+            (Some(continued), Some((_, last))) => last.push_str(continued),
+            _ => lines.push((number + 1, line.to_string())),
+        }
+    }
+    let (mut components, mut skipped) = (Vec::new(), Vec::new());
+    // components nested within an event, such as alarms, are passed over along with their contents
+    let mut current: Option<Component> = None;
+    let mut nested = 0;
+    for (number, line) in lines {
+        let property = match property_of(&line) {
+            Some(property) => property,
+            None => continue,
+        };
+        match (property.name.as_str(), &mut current) {
+            ("BEGIN", None) if property.value == "VEVENT" || property.value == "VTODO" => {
+                current = Some(Component { kind: property.value, line: number, properties: Vec::new() });
+            },
+            ("BEGIN", None) if property.value == "VJOURNAL" => skipped.push((property.value, number)),
+            ("BEGIN", Some(_)) => nested += 1,
+            ("END", Some(_)) if nested > 0 => nested -= 1,
+            ("END", Some(component)) if property.value == component.kind => components.extend(current.take()),
+            (_, Some(component)) if nested == 0 => component.properties.push(property),
+            _ => {},
+        }
+    }
+    (components, skipped)
+}
+
+/// Splits a content line into its name, parameters and value. Parameter values may be quoted, in
+/// which case they may contain colons and semicolons.
+fn property_of(line: &str) -> Option<Property> {
+    let mut quoted = false;
+    let colon = line.char_indices().find(|&(_, c)| {
+        if c == '"' { quoted = !quoted; }
+        c == ':' && !quoted
+    })?.0;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+    let mut parts = Vec::new();
+    let (mut quoted, mut from) = (false, 0);
+    for (i, c) in head.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                parts.push(&head[from..i]);
+                from = i + 1;
+            },
+            _ => {},
+        }
+    }
+    parts.push(&head[from..]);
+    let mut parts = parts.into_iter();
+    let name = parts.next()?.to_ascii_uppercase();
+    let parameters = parts
+        .filter_map(|part| part.split_once('='))
+        .map(|(key, value)| (key.to_ascii_uppercase(), value.trim_matches('"').to_string()))
+        .collect();
+    Some(Property { name, parameters, value: value.to_string() })
+}
+
+/// Makes an event repeat by an RRULE, counting the occurrences up to an UNTIL. Rules more frequent
+/// than daily become a fixed period. Returns what was wrong with the rule if it cannot be followed.
+fn repeat(event: &mut Event, rrule: &str) -> Result<(), String> {
+    let start = event.start.ok_or_else(|| "a repeating event needs a start".to_string())?;
+    let (mut count, mut until, mut parts) = (None, None, Vec::new());
+    for part in rrule.split(';').filter(|part| !part.is_empty()) {
+        match part.split_once('=').map(|(key, value)| (key.to_ascii_uppercase(), value)) {
+            Some((key, value)) if key == "COUNT" => count = Some(value.parse::<usize>().map_err(|_| format!("COUNT={} is not a number", value))?),
+            Some((key, value)) if key == "UNTIL" => {
+                until = Some(time_of(&Property { name: key, parameters: Vec::new(), value: value.to_string() })?.0);
+            },
+            // the start of the week only matters to rules that clamendar cannot follow anyway
+            Some((key, _)) if key == "WKST" => {},
+            _ => parts.push(part),
+        }
+    }
+    let rest = parts.join(";");
+    let period = match rest.to_ascii_uppercase().as_str() {
+        "FREQ=HOURLY" => Some(Duration::hours(1)),
+        "FREQ=MINUTELY" => Some(Duration::minutes(1)),
+        "FREQ=SECONDLY" => Some(Duration::seconds(1)),
+        rest => rest.split_once(";INTERVAL=").and_then(|(frequency, interval)| {
+            let interval = interval.parse::<i32>().ok()?;
+            match frequency {
+                "FREQ=HOURLY" => Some(Duration::hours(1) * interval),
+                "FREQ=MINUTELY" => Some(Duration::minutes(1) * interval),
+                "FREQ=SECONDLY" => Some(Duration::seconds(1) * interval),
+                _ => None,
+            }
+        }),
+    };
+    match period {
+        // a period of nothing or less would never move on to a next occurrence
+        Some(period) => match start.checked_add_signed(period).filter(|&end| end > start) {
+            Some(end) => event.interval = Interval::RepIndefinite(end),
+            None => return Err(format!("RRULE:{} does not move forward", rrule)),
+        },
+        None => {
+            // an unsupported rule leaves a single event, rather than one repeating every instant
+            let rule = rest.parse::<Recurrence>().map_err(|_| format!("RRULE:{} is not supported", rrule))?;
+            event.interval = Interval::RepIndefinite(start);
+            if !event.set_rule(rule) {
+                event.interval = Interval::None;
+                return Err(format!("RRULE:{} never occurs", rrule));
+            }
+        },
+    }
+    let end = match event.interval {
+        Interval::RepIndefinite(end) => end,
+        _ => return Ok(()),
+    };
+    let count = match (count, until) {
+        (Some(count), _) => Some(count),
+        (None, Some(until)) => Some(event.occurrences(start, until + Duration::seconds(1)).count()),
+        (None, None) => None,
+    };
+    match count {
+        Some(0) => {
+            event.interval = Interval::None;
+            event.rule = None;
+            Err(format!("RRULE:{} ends before it starts", rrule))
+        },
+        Some(occurrences) => {
+            event.interval = Interval::RepDefinite { occurrences, end };
+            Ok(())
+        },
+        None => Ok(()),
+    }
+}
+
+/// Describes how a repeating event repeats as an RRULE, without its count. Events without a rule
+/// repeat by the fixed duration between their start and end, in the largest unit that divides it.
+fn rule(event: &Event, start: DateTime<Local>, end: DateTime<Local>) -> String {
+    if let Some(rule) = &event.rule { return rule.to_string(); }
+    let seconds = (end - start).num_seconds().max(1);
+    let (frequency, interval) = [("DAILY", 86400), ("HOURLY", 3600), ("MINUTELY", 60), ("SECONDLY", 1)]
+        .iter()
+        .find(|(_, unit)| seconds % unit == 0)
+        .map(|(frequency, unit)| (*frequency, seconds / unit))
+        .unwrap_or(("SECONDLY", seconds));
+    if interval == 1 { format!("FREQ={}", frequency) } else { format!("FREQ={};INTERVAL={}", frequency, interval) }
+}
+
+/// Writes a property holding a time in the event's timezone, or only its date. See the module
+/// documentation.
+fn time(name: &str, time: DateTime<Local>, zone: Zone, dates: bool) -> String {
+    match zone {
+        Zone::Floating if dates => format!("{};VALUE=DATE:{}", name, time.format("%Y%m%d")),
+        Zone::Floating => format!("{}:{}", name, time.format("%Y%m%dT%H%M%S")),
+        Zone::Fixed(_) => format!("{}:{}", name, time.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ")),
+        Zone::Named(tz) => format!("{};TZID={}:{}", name, tz.name(), time.with_timezone(&tz).format("%Y%m%dT%H%M%S")),
+    }
+}
+
+/// Reads the time held by a property such as DTSTART, along with the timezone it is given in. A
+/// date stands for midnight in floating time.
+fn time_of(property: &Property) -> Result<(DateTime<Local>, Zone), String> {
+    let value = property.value.as_str();
+    let invalid = || format!("{} {} was not understood", property.name, value);
+    if value.len() == 8 {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d").map_err(|_| invalid())?;
+        let time = Local.from_local_datetime(&date.and_hms(0, 0, 0)).earliest().ok_or_else(invalid)?;
+        return Ok((time, Zone::Floating));
+    }
+    if let Some(utc) = value.strip_suffix('Z') {
+        let naive = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").map_err(|_| invalid())?;
+        return Ok((Utc.from_utc_datetime(&naive).with_timezone(&Local), Zone::Fixed(FixedOffset::east(0))));
+    }
+    let naive = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").map_err(|_| invalid())?;
+    let zone = match property.parameters.iter().find(|(key, _)| key == "TZID") {
+        Some((_, tzid)) => Zone::Named(zone_named(tzid).ok_or_else(|| format!("TZID {} is not a known IANA timezone", tzid))?),
+        None => Zone::Floating,
+    };
+    let time = zone.from_local_datetime(&naive).earliest().ok_or_else(invalid)?;
+    Ok((time.with_timezone(&Local), zone))
+}
+
+/// Returns the unescaped SUMMARY of a component, or an empty string.
+fn summary_of(component: &Component) -> String {
+    component.properties
+        .iter()
+        .find(|property| property.name == "SUMMARY")
+        .map(|property| unescape(&property.value))
+        .unwrap_or_default()
+}
+
+/// Undoes `escape`.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => {},
+        }
+    }
+    unescaped
+}
+
+/// Finds the IANA timezone named by a TZID, dropping leading path segments such as
+/// `/mozilla.org/20050126_1/` until a name is recognized.
+fn zone_named(tzid: &str) -> Option<Tz> {
+    let mut rest = tzid;
+    loop {
+        if let Ok(tz) = rest.parse::<Tz>() { return Some(tz); }
+        rest = rest.split_once('/')?.1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::recurrence::Frequency;
+    use super::*;
+
+    /// Wraps the lines of a VEVENT in a calendar and imports it.
+    fn import_event(lines: &[&str]) -> Import {
+        let mut calendar = vec!["BEGIN:VCALENDAR", "VERSION:2.0", "BEGIN:VEVENT", "UID:standup", "SUMMARY:standup"];
+        calendar.extend(lines);
+        calendar.extend(["END:VEVENT", "END:VCALENDAR", ""]);
+        import(&calendar.join("\r\n"), &[])
+    }
+
+    fn at(year: i32, month: u32, day: u32) -> DateTime<Local> {
+        Local.ymd(year, month, day).and_hms(9, 0, 0)
+    }
+
+    #[test]
+    fn calendar_rules() {
+        let import = import_event(&["DTSTART:20300131T090000", "RRULE:FREQ=MONTHLY;COUNT=3", "EXDATE:20300228T090000"]);
+        assert!(import.problems.is_empty(), "{:?}", import.problems);
+        let event = &import.events[0];
+        assert_eq!(event.rule.as_ref().map(|rule| (rule.frequency, rule.by_month_day)), Some((Frequency::Monthly, Some(31))));
+        assert!(matches!(event.interval, Interval::RepDefinite { occurrences: 3, .. }));
+        assert_eq!(event.exceptions, [at(2030, 2, 28)]);
+        let starts: Vec<_> = event.occurrences(at(2030, 1, 1), at(2031, 1, 1)).map(|(start, _)| start).collect();
+        assert_eq!(starts, [at(2030, 1, 31), at(2030, 3, 31)]);
+    }
+
+    #[test]
+    fn counted_and_fixed_rules() {
+        let until = import_event(&["DTSTART:20300101T090000", "RRULE:FREQ=DAILY;UNTIL=20300105T090000"]);
+        assert!(matches!(until.events[0].interval, Interval::RepDefinite { occurrences: 5, .. }));
+        // more often than daily, the rule becomes a fixed period
+        let hourly = import_event(&["DTSTART:20300101T090000", "RRULE:FREQ=HOURLY;INTERVAL=2"]);
+        let event = &hourly.events[0];
+        assert!(event.rule.is_none());
+        assert!(matches!(event.interval, Interval::RepIndefinite(end) if end == at(2030, 1, 1) + Duration::hours(2)));
+        assert!(export(&[event]).contains("RRULE:FREQ=HOURLY;INTERVAL=2\r\n"));
+    }
+
+    #[test]
+    fn intervals_that_do_not_move_forward() {
+        for interval in ["0", "-1"] {
+            let import = import_event(&["DTSTART:20300101T090000", &format!("RRULE:FREQ=HOURLY;INTERVAL={}", interval)]);
+            assert_eq!(import.problems.len(), 1);
+            assert!(import.problems[0].contains("does not move forward; only the first occurrence was imported"));
+            let event = &import.events[0];
+            assert!(matches!(event.interval, Interval::None));
+            assert!(!export(&[event]).contains("RRULE"));
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let import = import_event(&[
+            "DTSTART:20300104T090000",
+            "RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=FR,MO;COUNT=10",
+            "EXDATE:20300107T090000",
+        ]);
+        let calendar = export(&import.events.iter().collect::<Vec<&Event>>());
+        assert!(calendar.contains("RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=FR,MO;COUNT=10\r\n"));
+        let again = super::import(&calendar, &[]);
+        assert!(again.problems.is_empty(), "{:?}", again.problems);
+        let json = |events: &[Event]| serde_json::to_string(&events.iter().collect::<Vec<&Event>>()).unwrap();
+        assert_eq!(json(&again.events), json(&import.events));
+    }
+
+    #[test]
+    fn unsupported_rule() {
+        let import = import_event(&[
+            "DTSTART:20300101T090000",
+            "RRULE:FREQ=MONTHLY;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1",
+            "EXDATE:20300131T090000",
+        ]);
+        assert_eq!(import.problems.len(), 1);
+        assert!(import.problems[0].contains("is not supported; only the first occurrence was imported"));
+        // only the first occurrence is left, with nothing about it repeating
+        let event = &import.events[0];
+        assert_eq!(event.start, Some(at(2030, 1, 1)));
+        assert!(matches!(event.interval, Interval::None));
+        assert!(event.rule.is_none() && event.exceptions.is_empty());
+        let calendar = export(&[event]);
+        assert!(!calendar.contains("RRULE") && !calendar.contains("EXDATE"));
+    }
+}
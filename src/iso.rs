@@ -0,0 +1,539 @@
+//! Contains a parser for the ISO 8601 date and time representations accepted in the insert format.
+//!
+//! Dates may be calendar dates (`2021-02-01`, `20210201`), week dates (`2021-W05-1`, `2021W051`,
+//! or `2021-W05` for the Monday) or ordinal dates (`2021-032`, `2021032`). A time may follow after
+//! a `T` as `hh`, `hh:mm` or `hh:mm:ss` (or `hhmm`, `hhmmss`), where the last component may carry
+//! a decimal fraction, and `24:00` stands for the end of the day. Finally, a `Z` or a `±hh`,
+//! `±hh:mm` or `±hhmm` offset from UTC may be given.
+//!
+//! Durations are written as `PnYnMnWnDTnHnMnS`, leaving out any component that is zero, as in
+//! `P1W`, `PT1H30M` or `P1Y2M`. The seconds may carry a decimal fraction.
+
+use chrono::{
+    DateTime,
+    Datelike,
+    Duration,
+    FixedOffset,
+    NaiveDate,
+    NaiveDateTime,
+    NaiveTime,
+    TimeZone,
+    Weekday,
+};
+use serde::{Deserialize, Serialize};
+use std::{convert::TryFrom, fmt, str::FromStr};
+
+/// A date, with an optional time and offset from UTC, as written in ISO 8601.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IsoDateTime {
+    pub date: NaiveDate,
+    /// None if only a date was given.
+    pub time: Option<NaiveTime>,
+    /// None if the time is local.
+    pub offset: Option<FixedOffset>,
+}
+
+/// A duration as written in ISO 8601. Years, months, weeks and days are nominal: they follow the
+/// calendar and the local clock, so that P1M after January 31st is the last day of February and
+/// P1D is the same time of day tomorrow, DST changes notwithstanding. Hours, minutes and seconds
+/// are exact.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IsoDuration {
+    pub years: u32,
+    pub months: u32,
+    pub weeks: u32,
+    pub days: u32,
+    pub hours: u32,
+    pub minutes: u32,
+    pub seconds: u32,
+    pub nanoseconds: u32,
+}
+
+/// Describes what went wrong while parsing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    ExpectedDigit,
+    Expected(char),
+    ExpectedDesignator,
+    InvalidDate,
+    InvalidOffset,
+    InvalidTime,
+    TrailingCharacters,
+}
+
+/// Returned when a string is not valid ISO 8601, along with where parsing failed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParseError {
+    pub kind: ErrorKind,
+    /// The column, counting from 1, at which parsing failed.
+    pub column: usize,
+}
+
+impl IsoDateTime {
+    /// Combines the date and time, taking midnight if no time was given.
+    pub fn naive(&self) -> NaiveDateTime {
+        self.date.and_time(self.time.unwrap_or_else(|| NaiveTime::from_hms(0, 0, 0)))
+    }
+
+    /// Converts into the given timezone, interpreting times without an offset as wall-clock times
+    /// there. Returns None for times that do not exist, such as those skipped by a DST change.
+    pub fn in_zone<Tz: TimeZone>(&self, zone: &Tz) -> Option<DateTime<Tz>> {
+        match self.offset {
+            Some(offset) => offset.from_local_datetime(&self.naive()).single().map(|time| time.with_timezone(zone)),
+            None => zone.from_local_datetime(&self.naive()).earliest(),
+        }
+    }
+}
+
+impl IsoDuration {
+    /// Returns the time this long after `time`, or None if that local time does not exist. The
+    /// nominal part follows the clock of the timezone `time` is in.
+    pub fn add_to<Tz: TimeZone>(&self, time: DateTime<Tz>) -> Option<DateTime<Tz>> {
+        self.shift(time, 1)
+    }
+
+    /// Returns the time this long before `time`, or None if that local time does not exist.
+    pub fn subtract_from<Tz: TimeZone>(&self, time: DateTime<Tz>) -> Option<DateTime<Tz>> {
+        self.shift(time, -1)
+    }
+
+    /// Moves `time` by the duration, forwards or backwards depending on `sign`. The nominal part
+    /// is applied to the local date, clamping to the end of shorter months, before the exact part.
+    /// Returns None if the result is out of the range of dates that can be represented.
+    fn shift<Tz: TimeZone>(&self, time: DateTime<Tz>, sign: i32) -> Option<DateTime<Tz>> {
+        let naive = time.naive_local();
+        // none of these can overflow an i64, but the year may not fit a date
+        let months = naive.year() as i64 * 12 + naive.month0() as i64 + sign as i64 * (self.years as i64 * 12 + self.months as i64);
+        let (year, month) = (i32::try_from(months.div_euclid(12)).ok()?, months.rem_euclid(12) as u32 + 1);
+        let mut day = naive.day();
+        let date = loop {
+            match NaiveDate::from_ymd_opt(year, month, day) {
+                Some(date) => break date,
+                None if day > 28 => day -= 1,
+                None => return None,
+            }
+        };
+        let date = date.checked_add_signed(Duration::days(sign as i64 * (self.weeks as i64 * 7 + self.days as i64)))?;
+        let exact = Duration::hours(self.hours as i64)
+            + Duration::minutes(self.minutes as i64)
+            + Duration::seconds(self.seconds as i64)
+            + Duration::nanoseconds(self.nanoseconds as i64);
+        let shifted = time.timezone().from_local_datetime(&date.and_time(naive.time())).earliest()?;
+        if sign < 0 { shifted.checked_sub_signed(exact) } else { shifted.checked_add_signed(exact) }
+    }
+}
+
+// Durations are written back in ISO 8601, leaving out the components that are zero.
+impl fmt::Display for IsoDuration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "P")?;
+        for (amount, unit) in [(self.years, 'Y'), (self.months, 'M'), (self.weeks, 'W'), (self.days, 'D')] {
+            if amount != 0 { write!(f, "{}{}", amount, unit)?; }
+        }
+        if self.hours != 0 || self.minutes != 0 || self.seconds != 0 || self.nanoseconds != 0 {
+            write!(f, "T")?;
+            if self.hours != 0 { write!(f, "{}H", self.hours)?; }
+            if self.minutes != 0 { write!(f, "{}M", self.minutes)?; }
+            match (self.seconds, self.nanoseconds) {
+                (0, 0) => {},
+                (seconds, 0) => write!(f, "{}S", seconds)?,
+                (seconds, nanos) => write!(f, "{}.{}S", seconds, format!("{:09}", nanos).trim_end_matches('0'))?,
+            }
+        } else if *self == IsoDuration::default() {
+            write!(f, "T0S")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for IsoDuration {
+    type Err = ParseError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        parse_duration(string)
+    }
+}
+
+impl TryFrom<String> for IsoDuration {
+    type Error = ParseError;
+
+    fn try_from(string: String) -> Result<Self, Self::Error> {
+        string.parse()
+    }
+}
+
+impl From<IsoDuration> for String {
+    fn from(duration: IsoDuration) -> Self {
+        duration.to_string()
+    }
+}
+
+impl FromStr for IsoDateTime {
+    type Err = ParseError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        parse(string)
+    }
+}
+
+impl ParseError {
+    /// Moves the column right by `columns`, for strings that were cut out of a longer one.
+    pub fn shifted(self, columns: usize) -> Self {
+        ParseError { column: self.column + columns, ..self }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let problem = match self.kind {
+            ErrorKind::ExpectedDigit => String::from("expected a digit"),
+            ErrorKind::Expected(c) => format!("expected '{}'", c),
+            ErrorKind::ExpectedDesignator => String::from("expected a unit such as 'D' or 'H'"),
+            ErrorKind::InvalidDate => String::from("no such date"),
+            ErrorKind::InvalidOffset => String::from("invalid UTC offset"),
+            ErrorKind::InvalidTime => String::from("no such time"),
+            ErrorKind::TrailingCharacters => String::from("unexpected characters"),
+        };
+        write!(f, "{} at column {}", problem, self.column)
+    }
+}
+
+/// Attempts to parse an ISO 8601 date, optionally followed by a time and an offset.
+pub fn parse(string: &str) -> Result<IsoDateTime, ParseError> {
+    let mut cursor = Cursor { bytes: string.as_bytes(), position: 0 };
+    let date = cursor.date()?;
+    let time = if cursor.eat(b'T') {
+        Some(cursor.time()?)
+    } else {
+        None
+    };
+    let offset = match cursor.peek() {
+        Some(b'Z') | Some(b'+') | Some(b'-') if time.is_some() => Some(cursor.offset()?),
+        _ => None,
+    };
+    if cursor.peek().is_some() { return Err(cursor.error(ErrorKind::TrailingCharacters)); }
+    Ok(match time {
+        // 24:00 is midnight at the end of the day
+        Some((time, true)) => IsoDateTime { date: date + Duration::days(1), time: Some(time), offset },
+        Some((time, false)) => IsoDateTime { date, time: Some(time), offset },
+        None => IsoDateTime { date, time: None, offset },
+    })
+}
+
+/// Attempts to parse an ISO 8601 duration.
+pub fn parse_duration(string: &str) -> Result<IsoDuration, ParseError> {
+    let mut cursor = Cursor { bytes: string.as_bytes(), position: 0 };
+    if !cursor.eat(b'P') { return Err(cursor.error(ErrorKind::Expected('P'))); }
+    let mut duration = IsoDuration::default();
+    let mut in_time = false;
+    let mut any = false;
+    // designators must come in this order, and each at most once
+    let mut designators: &[u8] = b"YMWD";
+    while cursor.peek().is_some() {
+        if !in_time && cursor.eat(b'T') {
+            in_time = true;
+            designators = b"HMS";
+            if cursor.peek().is_none() { return Err(cursor.error(ErrorKind::ExpectedDigit)); }
+            continue;
+        }
+        let run = cursor.run_of_digits();
+        if run == 0 { return Err(cursor.error(ErrorKind::ExpectedDigit)); }
+        if run > 9 { return Err(cursor.error_at(cursor.position + 9, ErrorKind::ExpectedDesignator)); }
+        let amount = cursor.digits(run)?;
+        // only the seconds may carry a fraction
+        let mut nanos = 0;
+        if in_time && (cursor.peek() == Some(b'.') || cursor.peek() == Some(b',')) {
+            cursor.position += 1;
+            let run = cursor.run_of_digits();
+            if run == 0 { return Err(cursor.error(ErrorKind::ExpectedDigit)); }
+            for (i, digit) in cursor.bytes[cursor.position..cursor.position + run].iter().take(9).enumerate() {
+                nanos += (digit - b'0') as u32 * 10u32.pow(8 - i as u32);
+            }
+            cursor.position += run;
+            if cursor.peek() != Some(b'S') { return Err(cursor.error(ErrorKind::Expected('S'))); }
+        }
+        let designator = match cursor.peek() {
+            Some(c) => c,
+            None => return Err(cursor.error(ErrorKind::ExpectedDesignator)),
+        };
+        let index = match designators.iter().position(|&d| d == designator) {
+            Some(index) => index,
+            None => return Err(cursor.error(ErrorKind::ExpectedDesignator)),
+        };
+        designators = &designators[index + 1..];
+        cursor.position += 1;
+        any = true;
+        match (in_time, designator) {
+            (false, b'Y') => duration.years = amount,
+            (false, b'M') => duration.months = amount,
+            (false, b'W') => duration.weeks = amount,
+            (false, _) => duration.days = amount,
+            (true, b'H') => duration.hours = amount,
+            (true, b'M') => duration.minutes = amount,
+            (true, _) => {
+                duration.seconds = amount;
+                duration.nanoseconds = nanos;
+            },
+        }
+    }
+    if !any { return Err(cursor.error(ErrorKind::ExpectedDigit)); }
+    Ok(duration)
+}
+
+/// Attempts to parse a Z or a ±hh[[:]mm] offset from UTC on its own.
+pub fn parse_offset(string: &str) -> Result<FixedOffset, ParseError> {
+    let mut cursor = Cursor { bytes: string.as_bytes(), position: 0 };
+    let offset = cursor.offset()?;
+    if cursor.peek().is_some() { return Err(cursor.error(ErrorKind::TrailingCharacters)); }
+    Ok(offset)
+}
+
+/// Walks through the bytes of the string being parsed.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Cursor<'_> {
+    /// Parses a calendar, week or ordinal date in either basic or extended format.
+    fn date(&mut self) -> Result<NaiveDate, ParseError> {
+        let start = self.position;
+        let year = self.digits(4)? as i32;
+        let extended = self.eat(b'-');
+        let date = if self.eat(b'W') {
+            let week = self.digits(2)?;
+            // the day of the week defaults to Monday
+            let day = if (extended && self.eat(b'-')) || (!extended && self.run_of_digits() > 0) {
+                self.digits(1)?
+            } else {
+                1
+            };
+            let weekday = match day {
+                1 => Weekday::Mon,
+                2 => Weekday::Tue,
+                3 => Weekday::Wed,
+                4 => Weekday::Thu,
+                5 => Weekday::Fri,
+                6 => Weekday::Sat,
+                7 => Weekday::Sun,
+                _ => return Err(self.error_at(self.position - 1, ErrorKind::InvalidDate)),
+            };
+            NaiveDate::from_isoywd_opt(year, week, weekday)
+        } else {
+            match (extended, self.run_of_digits()) {
+                (_, 3) => NaiveDate::from_yo_opt(year, self.digits(3)?),
+                (true, 2) => {
+                    let month = self.digits(2)?;
+                    if !self.eat(b'-') { return Err(self.error(ErrorKind::Expected('-'))); }
+                    let day = self.digits(2)?;
+                    NaiveDate::from_ymd_opt(year, month, day)
+                },
+                (false, 4) => {
+                    let month = self.digits(2)?;
+                    let day = self.digits(2)?;
+                    NaiveDate::from_ymd_opt(year, month, day)
+                },
+                // point at the first character that breaks the expected form
+                (true, run) if run < 2 => return Err(self.error_at(self.position + run, ErrorKind::ExpectedDigit)),
+                (true, _) => return Err(self.error_at(self.position + 2, ErrorKind::Expected('-'))),
+                (false, run) if run < 3 => return Err(self.error_at(self.position + run, ErrorKind::ExpectedDigit)),
+                (false, _) => return Err(self.error_at(self.position + 4, ErrorKind::TrailingCharacters)),
+            }
+        };
+        date.ok_or_else(|| self.error_at(start, ErrorKind::InvalidDate))
+    }
+
+    /// Parses a time in either basic or extended format. Returns true alongside the time if it
+    /// was given as 24:00, the end of the day.
+    fn time(&mut self) -> Result<(NaiveTime, bool), ParseError> {
+        let start = self.position;
+        let hour = self.digits(2)?;
+        let mut minute = 0;
+        let mut second = 0;
+        let mut nanos = 0;
+        // the unit, in nanoseconds, of the last component given, which a fraction applies to
+        let mut unit: u64 = 3_600_000_000_000;
+        let extended = self.peek() == Some(b':');
+        let has_next = |cursor: &mut Self| if extended {
+            cursor.eat(b':')
+        } else {
+            cursor.peek().is_some_and(|c| c.is_ascii_digit())
+        };
+        if has_next(self) {
+            minute = self.digits(2)?;
+            unit = 60_000_000_000;
+            if has_next(self) {
+                second = self.digits(2)?;
+                unit = 1_000_000_000;
+                if second > 59 { return Err(self.error_at(self.position - 2, ErrorKind::InvalidTime)); }
+            }
+        }
+        if self.eat(b'.') || self.eat(b',') {
+            let fraction_start = self.position;
+            let run = self.run_of_digits();
+            if run == 0 { return Err(self.error(ErrorKind::ExpectedDigit)); }
+            let digits = &self.bytes[fraction_start..fraction_start + run];
+            // anything past nanosecond precision is dropped
+            let mut fraction: u64 = 0;
+            let mut scale: u64 = 1;
+            for digit in digits.iter().take(9) {
+                fraction = fraction * 10 + (digit - b'0') as u64;
+                scale *= 10;
+            }
+            self.position += run;
+            let extra = (unit as u128 * fraction as u128 / scale as u128) as u64;
+            let total = second as u64 * 1_000_000_000 + minute as u64 * 60_000_000_000 + extra;
+            minute = (total / 60_000_000_000) as u32;
+            second = (total / 1_000_000_000 % 60) as u32;
+            nanos = (total % 1_000_000_000) as u32;
+        }
+        let next_day = hour == 24;
+        let time = if next_day {
+            (minute == 0 && second == 0 && nanos == 0).then(|| NaiveTime::from_hms(0, 0, 0))
+        } else {
+            NaiveTime::from_hms_nano_opt(hour, minute, second, nanos)
+        };
+        match time {
+            Some(time) => Ok((time, next_day)),
+            None => Err(self.error_at(start, ErrorKind::InvalidTime)),
+        }
+    }
+
+    /// Parses a Z or a ±hh[[:]mm] offset from UTC.
+    fn offset(&mut self) -> Result<FixedOffset, ParseError> {
+        if self.eat(b'Z') { return Ok(FixedOffset::east(0)); }
+        let start = self.position;
+        let sign = if self.eat(b'+') {
+            1
+        } else if self.eat(b'-') {
+            -1
+        } else {
+            return Err(self.error(ErrorKind::Expected('Z')));
+        };
+        let hours = self.digits(2)? as i32;
+        let minutes = if self.eat(b':') || self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.digits(2)? as i32
+        } else {
+            0
+        };
+        if hours > 23 || minutes > 59 { return Err(self.error_at(start, ErrorKind::InvalidOffset)); }
+        FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+            .ok_or_else(|| self.error_at(start, ErrorKind::InvalidOffset))
+    }
+
+    /// Parses exactly `count` digits as a number.
+    fn digits(&mut self, count: usize) -> Result<u32, ParseError> {
+        let mut number = 0;
+        for _ in 0..count {
+            match self.peek() {
+                Some(c) if c.is_ascii_digit() => {
+                    number = number * 10 + (c - b'0') as u32;
+                    self.position += 1;
+                },
+                _ => return Err(self.error(ErrorKind::ExpectedDigit)),
+            }
+        }
+        Ok(number)
+    }
+
+    /// Consumes the given byte if it comes next.
+    fn eat(&mut self, byte: u8) -> bool {
+        if self.peek() == Some(byte) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error(&self, kind: ErrorKind) -> ParseError {
+        self.error_at(self.position, kind)
+    }
+
+    fn error_at(&self, position: usize, kind: ErrorKind) -> ParseError {
+        ParseError { kind, column: position + 1 }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    /// Counts the digits that come next, without consuming them.
+    fn run_of_digits(&self) -> usize {
+        self.bytes[self.position..].iter().take_while(|c| c.is_ascii_digit()).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd(year, month, day)
+    }
+
+    #[test]
+    fn week_dates() {
+        assert_eq!(parse("2021-W05-1").unwrap().date, date(2021, 2, 1));
+        assert_eq!(parse("2021W051").unwrap().date, date(2021, 2, 1));
+        assert_eq!(parse("2021-W05").unwrap().date, date(2021, 2, 1));
+        assert_eq!(parse("2021-W05-7").unwrap().date, date(2021, 2, 7));
+        // the first week of 2021 starts in 2021, but that of 2020 in 2019
+        assert_eq!(parse("2020-W01-1").unwrap().date, date(2019, 12, 30));
+        assert_eq!(parse("2021-W05-8").unwrap_err().kind, ErrorKind::InvalidDate);
+        assert_eq!(parse("2021-W54").unwrap_err().kind, ErrorKind::InvalidDate);
+    }
+
+    #[test]
+    fn ordinal_dates() {
+        assert_eq!(parse("2021-032").unwrap().date, date(2021, 2, 1));
+        assert_eq!(parse("2021032").unwrap().date, date(2021, 2, 1));
+        assert_eq!(parse("2020-366").unwrap().date, date(2020, 12, 31));
+        assert_eq!(parse("2021-366").unwrap_err(), ParseError { kind: ErrorKind::InvalidDate, column: 1 });
+    }
+
+    #[test]
+    fn offsets() {
+        let parsed = parse("2021-02-01T09:30+05:30").unwrap();
+        assert_eq!(parsed.offset, Some(FixedOffset::east(5 * 3600 + 30 * 60)));
+        assert_eq!(parsed.in_zone(&Utc), Some(Utc.ymd(2021, 2, 1).and_hms(4, 0, 0)));
+        assert_eq!(parse("20210201T0930-0800").unwrap().offset, Some(FixedOffset::west(8 * 3600)));
+        assert_eq!(parse("2021-02-01T09:30-08").unwrap().offset, Some(FixedOffset::west(8 * 3600)));
+        assert_eq!(parse("2021-02-01T09:30Z").unwrap().offset, Some(FixedOffset::east(0)));
+        assert_eq!(parse("2021-02-01T09:30").unwrap().offset, None);
+        assert_eq!(parse("2021-02-01T09:30+24:00").unwrap_err().kind, ErrorKind::InvalidOffset);
+        // an offset needs a time to apply to
+        assert_eq!(parse("2021-02-01Z").unwrap_err().kind, ErrorKind::TrailingCharacters);
+    }
+
+    #[test]
+    fn end_of_day() {
+        let parsed = parse("2021-12-31T24:00").unwrap();
+        assert_eq!(parsed.naive(), date(2022, 1, 1).and_hms(0, 0, 0));
+        assert_eq!(parse("2021-12-31T2400").unwrap().naive(), date(2022, 1, 1).and_hms(0, 0, 0));
+        assert_eq!(parse("2021-12-31T24:01").unwrap_err(), ParseError { kind: ErrorKind::InvalidTime, column: 12 });
+        assert_eq!(parse("2021-12-31T25:00").unwrap_err().kind, ErrorKind::InvalidTime);
+    }
+
+    #[test]
+    fn fractions() {
+        assert_eq!(parse("2021-02-01T09:30.5").unwrap().time, Some(NaiveTime::from_hms(9, 30, 30)));
+        assert_eq!(parse("2021-02-01T09,25").unwrap().time, Some(NaiveTime::from_hms(9, 15, 0)));
+        assert_eq!(parse_duration("PT1.5S").unwrap(), IsoDuration { seconds: 1, nanoseconds: 500_000_000, ..Default::default() });
+        assert_eq!(parse_duration("P1DT").unwrap_err().kind, ErrorKind::ExpectedDigit);
+        assert_eq!(parse_duration("P1D1Y").unwrap_err().kind, ErrorKind::ExpectedDesignator);
+    }
+
+    #[test]
+    fn duration_out_of_range() {
+        let time = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+        for duration in ["P999999999W", "P999999999Y", "P999999999D"] {
+            let duration = parse_duration(duration).unwrap();
+            assert_eq!(duration.add_to(time), None, "{}", duration);
+            assert_eq!(duration.subtract_from(time), None, "{}", duration);
+        }
+        assert_eq!(parse_duration("P1M").unwrap().add_to(Utc.ymd(2021, 1, 31).and_hms(0, 0, 0)), Some(Utc.ymd(2021, 2, 28).and_hms(0, 0, 0)));
+    }
+}
@@ -1,6 +1,16 @@
-use chrono::{DateTime, Local};
+pub mod filter;
+pub mod ical;
+pub mod iso;
+pub mod recurrence;
+pub mod relative;
+pub mod storage;
+pub mod zone;
+
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, TimeZone};
+use crate::{iso::IsoDuration, recurrence::{Frequency, Recurrence}, zone::Zone};
 use serde::{Deserialize, Serialize};
-use std::cmp::Ordering;
+use std::{cmp::Ordering, convert::TryFrom};
+use uuid::Uuid;
 
 /// Describes the type of interval that an Event possesses. Note that 'None' is a value of this
 /// enum.
@@ -17,43 +27,395 @@ pub enum Interval {
 
 /// The universal event struct.
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(from = "StoredEvent")]
 pub struct Event {
     pub start: Option<DateTime<Local>>,
     pub interval: Interval,
     pub description: String,
+    /// Calendar-aware rule by which a repeating event advances. Without one, the event advances by
+    /// the fixed duration between its start and end.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<Recurrence>,
+    /// Occurrences of a repeating event that are skipped, by their original start.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exceptions: Vec<DateTime<Local>>,
+    /// Occurrences of a repeating event that were moved or reworded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overrides: Vec<Override>,
+    /// Remembers that the interval was entered as a duration rather than as an end.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub span: Option<Span>,
+    /// The timezone in which the event was entered, and by whose clock it repeats.
+    #[serde(default, skip_serializing_if = "Zone::is_floating")]
+    pub zone: Zone,
+    /// Identifies the event for as long as it exists, through edits, saving, and export and
+    /// import, unlike its time and description.
+    pub id: Uuid,
+}
+
+/// Describes on which side of the slash a duration was entered, as in `start/P1W` or `PT2H/end`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum Span {
+    After(IsoDuration),
+    Before(IsoDuration),
+}
+
+/// Replaces a single occurrence of a repeating event.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Override {
+    /// The original start of the replaced occurrence.
+    pub occurrence: DateTime<Local>,
+    pub start: DateTime<Local>,
+    pub description: String,
 }
 
 impl Event {
-    // TODO: actually use this in the implementation to keep repeats up-to-date.
-    /// Cycles the start and end times of the interval. Applies only to in/definite repeating intervals.
+    /// Cycles the start and end times of the interval. Applies only to in/definite repeating
+    /// intervals. Skipped occurrences are passed over, but still count towards a definite number of
+    /// repetitions.
     pub fn advance(&mut self) {
-        match self.interval {
-            Interval::RepDefinite { occurrences, end } => {
-                let duration = end - self.start.unwrap();
-                self.start = Some(end);
-                self.interval = Interval::RepDefinite {
-                    occurrences: occurrences - 1,
-                    end: end + duration,
-                };
+        let mut start = match self.start {
+            Some(start) => start,
+            None => return,
+        };
+        loop {
+            match self.interval {
+                Interval::RepDefinite { occurrences, end } => {
+                    self.interval = Interval::RepDefinite {
+                        occurrences: occurrences.saturating_sub(1),
+                        end: next_occurrence(&self.rule, self.zone, start, end),
+                    };
+                    self.start = Some(end);
+                },
+                Interval::RepIndefinite(end) => {
+                    self.interval = Interval::RepIndefinite(next_occurrence(&self.rule, self.zone, start, end));
+                    self.start = Some(end);
+                },
+                _ => return,
+            }
+            let previous = start;
+            start = self.start.unwrap();
+            if start <= previous
+                || !self.exceptions.contains(&start)
+                || matches!(self.interval, Interval::RepDefinite { occurrences: 0, .. })
+            {
+                break;
+            }
+        }
+        // forget about skipped and replaced occurrences that are now in the past
+        self.exceptions.retain(|&exception| exception >= start);
+        self.overrides.retain(|replacement| replacement.occurrence >= start);
+    }
+
+    /// Advances a repeating event until its next occurrence is upcoming. Returns false if a
+    /// definite repeating event runs out of occurrences along the way, in which case the caller
+    /// should drop it.
+    pub fn advance_to_upcoming(&mut self) -> bool {
+        // jump straight past the occurrences that are over, short of any moved into the future
+        let now = Local::now();
+        let limit = self.overrides
+            .iter()
+            .filter(|replacement| replacement.start > now)
+            .map(|replacement| replacement.occurrence)
+            .fold(now, DateTime::min);
+        if !self.skip_to(limit) { return false; }
+        while !self.is_upcoming() {
+            match (self.start, &self.interval) {
+                // a non-positive period would never catch up to the present
+                (Some(start), Interval::RepDefinite { end, .. })
+                    | (Some(start), Interval::RepIndefinite(end))
+                    if *end <= start
+                => break,
+                (Some(_), Interval::RepDefinite { .. })
+                    | (Some(_), Interval::RepIndefinite(_))
+                => self.advance(),
+                _ => break,
+            }
+            if let Interval::RepDefinite { occurrences: 0, .. } = self.interval { return false; }
+        }
+        true
+    }
+
+    /// Returns the override replacing the current occurrence of a repeating event, if any.
+    pub fn current_override(&self) -> Option<&Override> {
+        let start = self.start?;
+        self.overrides.iter().find(|replacement| replacement.occurrence == start)
+    }
+
+    /// Skips the current occurrence of a repeating event and advances to the next one. Returns
+    /// false if a definite repeating event has no occurrences left, in which case the caller should
+    /// drop it.
+    pub fn except_current(&mut self) -> bool {
+        let start = match (self.start, &self.interval) {
+            (Some(start), Interval::RepDefinite { .. }) | (Some(start), Interval::RepIndefinite(_)) => start,
+            _ => return true,
+        };
+        self.exceptions.push(start);
+        self.advance();
+        !matches!(self.interval, Interval::RepDefinite { occurrences: 0, .. })
+    }
+
+    /// Describes the repetition of a repeating event in calendar terms if it has no rule yet and
+    /// one can be inferred from its start and end. See `Recurrence::infer`.
+    pub fn infer_rule(&mut self) {
+        match (self.start, &self.interval, &self.rule) {
+            (Some(start), Interval::RepDefinite { end, .. }, None)
+                | (Some(start), Interval::RepIndefinite(end), None)
+            => self.rule = Recurrence::infer(start.with_timezone(&self.zone), end.with_timezone(&self.zone)),
+            _ => {},
+        }
+    }
+
+    /// Returns an untimed event with the given description and a new id, to be filled in from there.
+    pub fn new(description: String) -> Self {
+        Event {
+            start: None,
+            interval: Interval::None,
+            description,
+            rule: None,
+            exceptions: Vec::new(),
+            overrides: Vec::new(),
+            span: None,
+            zone: Zone::Floating,
+            id: Uuid::new_v4(),
+        }
+    }
+
+    /// Returns a lazy iterator over the occurrences of the event as (start, end) pairs, without
+    /// modifying the event. For repeating events, each pair is an occurrence and the one after it,
+    /// just as `advance` would leave them, and only occurrences starting within [from, to) are
+    /// yielded. Skipped occurrences are left out and overridden ones take their override's start.
+    /// Standard intervals are yielded once if they overlap the range, single events once if they
+    /// fall within it, and untimed events never.
+    pub fn occurrences(&self, from: DateTime<Local>, to: DateTime<Local>) -> Occurrences<'_> {
+        let mut occurrences = Occurrences {
+            event: self,
+            remaining: Some(0),
+            next: None,
+            to,
+        };
+        let (start, end) = match (self.start, &self.interval) {
+            (None, _) => return occurrences,
+            (Some(start), Interval::RepDefinite { occurrences: count, end }) => {
+                occurrences.remaining = Some(*count);
+                (start, *end)
+            },
+            (Some(start), Interval::RepIndefinite(end)) => {
+                occurrences.remaining = None;
+                (start, *end)
+            },
+            (Some(start), Interval::Standard(end)) => {
+                if *end > from && start < to {
+                    occurrences.next = Some((start, *end));
+                    occurrences.remaining = Some(1);
+                }
+                return occurrences;
+            },
+            (Some(start), Interval::None) => {
+                if start >= from && start < to {
+                    occurrences.next = Some((start, start));
+                    occurrences.remaining = Some(1);
+                }
+                return occurrences;
+            },
+        };
+        // a non-positive period would repeat the same occurrence forever
+        if end <= start {
+            occurrences.remaining = Some(0);
+            return occurrences;
+        }
+        occurrences.next = Some((start, end));
+        if from <= start { return occurrences; }
+        // skip ahead to the first occurrence at or after `from`
+        match (&self.rule, occurrences.remaining) {
+            (Some(rule), None) => {
+                let zone = self.zone;
+                if let Some(first) = rule.at_or_after(start.with_timezone(&zone), from.with_timezone(&zone)) {
+                    let first = first.with_timezone(&Local);
+                    occurrences.next = Some((first, next_occurrence(&self.rule, zone, start, first)));
+                }
             },
-            Interval::RepIndefinite(end) => {
-                let start = self.start.unwrap();
-                self.start = Some(end);
-                self.interval = Interval::RepIndefinite(end + (end - start));
+            (Some(_), Some(_)) => while let Some((start, _)) = occurrences.next {
+                if start >= from || occurrences.remaining == Some(0) { break; }
+                occurrences.step();
             },
+            (None, remaining) => {
+                let elapsed = (from - start).num_seconds();
+                let period = (end - start).num_seconds().max(1);
+                let skip = (elapsed + period - 1) / period;
+                occurrences.remaining = remaining.map(|count| count.saturating_sub(usize::try_from(skip).unwrap_or(usize::MAX)));
+                // an occurrence past the representable times never comes
+                occurrences.next = nth_occurrence(start, end, skip);
+            },
+        }
+        occurrences
+    }
+
+    /// Moves or rewords the current occurrence of a repeating event, replacing any earlier
+    /// override of it.
+    pub fn override_current(&mut self, start: DateTime<Local>, description: String) {
+        let occurrence = match (self.start, &self.interval) {
+            (Some(occurrence), Interval::RepDefinite { .. }) | (Some(occurrence), Interval::RepIndefinite(_)) => occurrence,
+            _ => return,
+        };
+        self.overrides.retain(|replacement| replacement.occurrence != occurrence);
+        self.overrides.push(Override { occurrence, start, description });
+    }
+
+    /// Makes a repeating event follow the given rule, moving its start to the first occurrence of
+    /// the rule at or after it. A monthly or yearly rule that names no day is pinned to the day
+    /// and month of the start. Returns false, leaving the event untouched, if the event does not
+    /// repeat or the rule never occurs.
+    pub fn set_rule(&mut self, mut rule: Recurrence) -> bool {
+        let start = match (self.start, &self.interval) {
+            (Some(start), Interval::RepDefinite { .. }) | (Some(start), Interval::RepIndefinite(_)) => start,
+            _ => return false,
+        };
+        let start = start.with_timezone(&self.zone);
+        // otherwise each occurrence would take its day from the one before, so that an event on
+        // the 31st would stay on the 28th after February
+        if let Frequency::Monthly | Frequency::Yearly = rule.frequency {
+            if rule.by_day.is_empty() && rule.by_month_day.is_none() { rule.by_month_day = Some(start.day() as i8); }
+        }
+        if rule.frequency == Frequency::Yearly && rule.by_month.is_none() { rule.by_month = Some(start.month()); }
+        let (first, second) = match rule.at_or_after(start, start) {
+            Some(first) => match rule.after(first, first) {
+                Some(second) => (first.with_timezone(&Local), second.with_timezone(&Local)),
+                None => return false,
+            },
+            None => return false,
+        };
+        self.start = Some(first);
+        match &mut self.interval {
+            Interval::RepDefinite { end, .. } | Interval::RepIndefinite(end) => *end = second,
             _ => {},
         }
+        self.rule = Some(rule);
+        true
+    }
+
+    /// Moves a repeating event on to its first occurrence at or after `time` in one step, as
+    /// `advance` would one occurrence at a time. Definite repeating events with a rule are left
+    /// alone, since their count can only be kept by stepping, which that count bounds. Returns
+    /// false if a definite repeating event runs out of occurrences on the way, or if the first
+    /// occurrence at or after `time` is past the representable times.
+    fn skip_to(&mut self, time: DateTime<Local>) -> bool {
+        let (start, end) = match (self.start, &self.interval) {
+            (Some(start), Interval::RepDefinite { end, .. })
+                | (Some(start), Interval::RepIndefinite(end))
+                if start < time && *end > start
+            => (start, *end),
+            _ => return true,
+        };
+        let (first, next) = match (&self.rule, &mut self.interval) {
+            (Some(rule), Interval::RepIndefinite(_)) => {
+                let zone = self.zone;
+                match rule.at_or_after(start.with_timezone(&zone), time.with_timezone(&zone)) {
+                    Some(first) => {
+                        let first = first.with_timezone(&Local);
+                        (first, next_occurrence(&self.rule, zone, start, first))
+                    },
+                    None => return true,
+                }
+            },
+            (Some(_), _) => return true,
+            (None, interval) => {
+                let elapsed = (time - start).num_seconds();
+                let period = (end - start).num_seconds().max(1);
+                let skip = (elapsed + period - 1) / period;
+                if let Interval::RepDefinite { occurrences, .. } = interval {
+                    match usize::try_from(skip).ok().and_then(|skip| occurrences.checked_sub(skip)) {
+                        Some(remaining) if remaining > 0 => *occurrences = remaining,
+                        _ => return false,
+                    }
+                }
+                match nth_occurrence(start, end, skip) {
+                    Some(occurrence) => occurrence,
+                    None => return false,
+                }
+            },
+        };
+        self.start = Some(first);
+        match &mut self.interval {
+            Interval::RepDefinite { end, .. } | Interval::RepIndefinite(end) => *end = next,
+            _ => {},
+        }
+        self.exceptions.retain(|&exception| exception >= first);
+        self.overrides.retain(|replacement| replacement.occurrence >= first);
+        // a skipped occurrence is passed over as advancing onto it would have
+        if self.exceptions.contains(&first) {
+            self.advance();
+            if let Interval::RepDefinite { occurrences: 0, .. } = self.interval { return false; }
+        }
+        true
     }
 
-    /// Returns true if the event, or the end of the interval, has yet to happen.
+    /// Returns true if the event, or the end of the interval, has yet to happen. An override of
+    /// the current occurrence takes the place of the start.
     pub fn is_upcoming(&self) -> bool {
-        match self.start {
-            Some(time) => Local::now() < time,
+        match (self.current_override(), self.start) {
+            (Some(replacement), _) => Local::now() < replacement.start,
+            (None, Some(time)) => Local::now() < time,
             _ => true, // untimed events are always considered upcoming
         }
     }
 }
 
+/// Lazily yields the occurrences of an Event within a range. See `Event::occurrences`.
+pub struct Occurrences<'a> {
+    event: &'a Event,
+    // None for indefinitely repeating events
+    remaining: Option<usize>,
+    next: Option<(DateTime<Local>, DateTime<Local>)>,
+    to: DateTime<Local>,
+}
+
+impl Occurrences<'_> {
+    /// Moves on to the following occurrence, using up one of the remaining ones.
+    fn step(&mut self) {
+        if let Some((start, end)) = self.next {
+            self.next = Some((end, next_occurrence(&self.event.rule, self.event.zone, start, end)));
+        }
+        if let Some(count) = self.remaining {
+            self.remaining = Some(count.saturating_sub(1));
+        }
+    }
+}
+
+impl Iterator for Occurrences<'_> {
+    type Item = (DateTime<Local>, DateTime<Local>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (start, end) = self.next?;
+            if self.remaining == Some(0) || start >= self.to { return None; }
+            self.step();
+            if self.event.exceptions.contains(&start) { continue; }
+            return match self.event.overrides.iter().find(|replacement| replacement.occurrence == start) {
+                Some(replacement) => Some((replacement.start, end)),
+                None => Some((start, end)),
+            };
+        }
+    }
+}
+
+/// Given two consecutive occurrences, returns the one after them, using the rule if there is one
+/// and the fixed duration between them otherwise. Rules follow the clock of the given timezone.
+fn next_occurrence(rule: &Option<Recurrence>, zone: Zone, start: DateTime<Local>, end: DateTime<Local>) -> DateTime<Local> {
+    rule.as_ref()
+        .and_then(|rule| rule.after(end.with_timezone(&zone), end.with_timezone(&zone)))
+        .map(|next| next.with_timezone(&Local))
+        .unwrap_or_else(|| end + (end - start))
+}
+
+/// Given two consecutive occurrences, returns the one `skip` fixed durations between them after
+/// the first, along with the one after it, or None if either is past the representable times.
+fn nth_occurrence(start: DateTime<Local>, end: DateTime<Local>, skip: i64) -> Option<(DateTime<Local>, DateTime<Local>)> {
+    let period = end - start;
+    let first = start.checked_add_signed(Duration::milliseconds(period.num_milliseconds().checked_mul(skip)?))?;
+    Some((first, first.checked_add_signed(period)?))
+}
+
 // Events are ordered chronologically first, with untimed events coming last, then alphabetically
 // by description.
 impl Ord for Event {
@@ -69,7 +431,7 @@ impl Ord for Event {
                 (Interval::None, Interval::RepDefinite { end: other_end, .. })
                     | (Interval::None, Interval::RepIndefinite(other_end))
                     | (Interval::None, Interval::Standard(other_end))
-                    => self_time.cmp(&other_end),
+                    => self_time.cmp(other_end),
                 (Interval::RepDefinite { end: self_end, .. }, Interval::RepDefinite { end: other_end, .. })
                     | (Interval::RepDefinite { end: self_end, .. }, Interval::RepIndefinite(other_end))
                     | (Interval::RepDefinite { end: self_end, .. }, Interval::Standard(other_end))
@@ -79,7 +441,7 @@ impl Ord for Event {
                     | (Interval::Standard(self_end), Interval::RepDefinite { end: other_end, .. })
                     | (Interval::Standard(self_end), Interval::RepIndefinite(other_end))
                     | (Interval::Standard(self_end), Interval::Standard(other_end))
-                    => self_end.cmp(&other_end),
+                    => self_end.cmp(other_end),
             },
             (Some(_), None) => Ordering::Less,
             (None, Some(_)) => Ordering::Greater,
@@ -106,3 +468,164 @@ impl PartialEq<Self> for Event {
 }
 
 impl Eq for Event {}
+
+/// An Event as it is read from storage, with each time still at the offset it was saved with.
+/// Times of floating events keep their wall-clock time when read back elsewhere; all others keep
+/// their instant.
+#[derive(Deserialize)]
+struct StoredEvent {
+    start: Option<DateTime<FixedOffset>>,
+    interval: StoredInterval,
+    description: String,
+    #[serde(default)]
+    rule: Option<Recurrence>,
+    #[serde(default)]
+    exceptions: Vec<DateTime<FixedOffset>>,
+    #[serde(default)]
+    overrides: Vec<StoredOverride>,
+    #[serde(default)]
+    span: Option<Span>,
+    #[serde(default)]
+    zone: Zone,
+    // events written by hand may leave it out
+    #[serde(default = "Uuid::new_v4")]
+    id: Uuid,
+}
+
+#[derive(Deserialize)]
+enum StoredInterval {
+    RepDefinite {
+        occurrences: usize,
+        end: DateTime<FixedOffset>,
+    },
+    RepIndefinite(DateTime<FixedOffset>),
+    Standard(DateTime<FixedOffset>),
+    None,
+}
+
+#[derive(Deserialize)]
+struct StoredOverride {
+    occurrence: DateTime<FixedOffset>,
+    start: DateTime<FixedOffset>,
+    description: String,
+}
+
+impl From<StoredEvent> for Event {
+    fn from(stored: StoredEvent) -> Self {
+        let zone = stored.zone;
+        let resolve = |time: DateTime<FixedOffset>| match zone {
+            Zone::Floating => Local.from_local_datetime(&time.naive_local())
+                .earliest()
+                .unwrap_or_else(|| time.with_timezone(&Local)),
+            _ => time.with_timezone(&Local),
+        };
+        Event {
+            start: stored.start.map(resolve),
+            interval: match stored.interval {
+                StoredInterval::RepDefinite { occurrences, end } => Interval::RepDefinite { occurrences, end: resolve(end) },
+                StoredInterval::RepIndefinite(end) => Interval::RepIndefinite(resolve(end)),
+                StoredInterval::Standard(end) => Interval::Standard(resolve(end)),
+                StoredInterval::None => Interval::None,
+            },
+            description: stored.description,
+            rule: stored.rule,
+            exceptions: stored.exceptions.into_iter().map(resolve).collect(),
+            overrides: stored.overrides
+                .into_iter()
+                .map(|replacement| Override {
+                    occurrence: resolve(replacement.occurrence),
+                    start: resolve(replacement.start),
+                    description: replacement.description,
+                })
+                .collect(),
+            span: stored.span,
+            zone,
+            id: stored.id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hourly(start: DateTime<Local>, occurrences: usize) -> Event {
+        Event {
+            start: Some(start),
+            interval: Interval::RepDefinite { occurrences, end: start + Duration::hours(1) },
+            ..Event::new(String::from("hourly"))
+        }
+    }
+
+    #[test]
+    fn advance_to_upcoming_skips_ahead() {
+        let start = Local::now() - Duration::hours(1000) + Duration::minutes(30);
+        let mut event = hourly(start, 2000);
+        // the first upcoming occurrence is skipped, and so passed over like any other
+        event.exceptions.push(start + Duration::hours(1000));
+        assert!(event.advance_to_upcoming());
+        assert_eq!(event.start, Some(start + Duration::hours(1001)));
+        match event.interval {
+            Interval::RepDefinite { occurrences, end } => {
+                assert_eq!(occurrences, 999);
+                assert_eq!(end, start + Duration::hours(1002));
+            },
+            _ => panic!("the interval changed kind"),
+        }
+        assert!(event.exceptions.is_empty());
+    }
+
+    #[test]
+    fn advance_to_upcoming_stops_at_moved_occurrence() {
+        let start = Local::now() - Duration::hours(10) + Duration::minutes(30);
+        let mut event = hourly(start, 100);
+        let occurrence = start + Duration::hours(3);
+        event.overrides.push(Override { occurrence, start: Local::now() + Duration::days(1), description: String::new() });
+        assert!(event.advance_to_upcoming());
+        assert_eq!(event.start, Some(occurrence));
+    }
+
+    #[test]
+    fn monthly_rule_keeps_the_day() {
+        let start = Local.ymd(2021, 1, 31).and_hms(9, 0, 0);
+        let mut event = hourly(start, 10);
+        assert!(event.set_rule("FREQ=MONTHLY".parse().unwrap()));
+        let starts: Vec<_> = event.occurrences(start, Local.ymd(2021, 5, 1).and_hms(0, 0, 0)).map(|(start, _)| start.date()).collect();
+        assert_eq!(starts, [Local.ymd(2021, 1, 31), Local.ymd(2021, 2, 28), Local.ymd(2021, 3, 31), Local.ymd(2021, 4, 30)]);
+        event.advance();
+        event.advance();
+        assert_eq!(event.start.map(|start| start.date()), Some(Local.ymd(2021, 3, 31)));
+    }
+
+    #[test]
+    fn yearly_rule_keeps_leap_day() {
+        let start = Local.ymd(2024, 2, 29).and_hms(9, 0, 0);
+        let mut event = hourly(start, 10);
+        assert!(event.set_rule("FREQ=YEARLY".parse().unwrap()));
+        for _ in 0..4 { event.advance(); }
+        assert_eq!(event.start.map(|start| start.date()), Some(Local.ymd(2028, 2, 29)));
+    }
+
+    #[test]
+    fn advance_to_upcoming_drops_exhausted() {
+        let mut event = hourly(Local::now() - Duration::hours(10), 5);
+        assert!(!event.advance_to_upcoming());
+    }
+
+    #[test]
+    fn skipping_more_occurrences_than_fit_in_32_bits() {
+        let start = Local.ymd(1900, 1, 1).and_hms(0, 0, 0);
+        let mut event = Event {
+            start: Some(start),
+            interval: Interval::RepIndefinite(start + Duration::seconds(1)),
+            ..Event::new(String::from("every second"))
+        };
+        let from = Local.ymd(2021, 6, 1).and_hms(0, 0, 0);
+        assert_eq!(event.occurrences(from, from + Duration::seconds(2)).collect::<Vec<_>>(), [
+            (from, from + Duration::seconds(1)),
+            (from + Duration::seconds(1), from + Duration::seconds(2)),
+        ]);
+        assert!(event.advance_to_upcoming());
+        assert!(event.start.is_some_and(|start| start >= Local::now() - Duration::seconds(1)));
+    }
+}
@@ -1,47 +1,98 @@
+mod backup;
+mod cli;
 mod config;
 mod error;
 mod state;
+mod sync;
+mod watch;
 
-use chrono::{DateTime, Local, LocalResult, Timelike, TimeZone};
-use clamendar::{self, Event, Interval};
+use chrono::{DateTime, Local, Timelike};
+use clamendar::{
+    self,
+    filter::Filter,
+    recurrence::Recurrence,
+    storage::{json::{self, JsonFile}, Storage},
+    zone::Zone,
+    Event,
+    Interval,
+    Override,
+    Span,
+};
 use crate::{
-    config::*,
+    config::{Backend, Command, Config, TICK},
     error::Error,
-    state::{ Focus, State },
+    state::{ Focus, Scope, State },
+    sync::Snapshot,
+    watch::Watcher,
 };
 use crossterm::{
+    cursor,
     event::{self, KeyCode},
     terminal,
 };
-use std::io;
+use std::io::{self, Write};
 use std::fs;
+use std::panic;
+use std::process;
+use std::path::Path;
 use tui::{
     backend::CrosstermBackend,
     layout::{Constraint, Direction, Layout},
     style::{Color, Modifier, Style},
     Terminal,
-    widgets::{Block, Borders, List, ListItem, Paragraph, Row, Table},
+    widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Row, Table},
 };
 
-fn main() -> Result<(), Error> {
-    let mut s = State::default();
-    // split all deserialized events into vectors: one per pane
-    for event in deserialize()? {
-        match (&event.start, &event.interval) {
-            (Some(_), Interval::None)
-                | (Some(_), Interval::RepDefinite { .. })
-                | (Some(_), Interval::RepIndefinite(_))
-            => s.timed.push(event),
-            (Some(_), _) => s.intervals.push(event),
-            (None, _) => s.untimed.push(event),
-        }
+fn main() {
+    match run() {
+        // the output was cut short on purpose, as by `clamendar list | head -1`
+        Err(Error::Io(error)) if error.kind() == io::ErrorKind::BrokenPipe => {},
+        Err(error) => {
+            eprintln!("clamendar: {}", error);
+            process::exit(error.exit_code());
+        },
+        Ok(()) => {},
+    }
+}
+
+/// Carries out the command given on the command line, or runs the interface until it is quit.
+fn run() -> Result<(), Error> {
+    let (config, command) = Config::load()?;
+    match command {
+        Command::Add(record) => return cli::add(&config, &record),
+        Command::Agenda(days, filter) => return cli::agenda(&config, days, filter.as_ref()),
+        Command::Delete(id) => return cli::delete(&config, &id),
+        Command::Edit(id, record) => return cli::edit(&config, &id, record.as_deref()),
+        Command::Export(path) => return cli::export(&config, path.as_deref()),
+        Command::Import(path) => return cli::import(&config, &path),
+        Command::Interface => {},
+        Command::List(listing) => return cli::list(&config, &listing),
+        Command::ListBackups => {
+            let current = config.storage().and_then(|mut storage| Ok(storage.load()?)).map_or(0, |events| events.len());
+            let mut out = io::stdout().lock();
+            for (number, backup) in backup::list(&config)?.iter().enumerate() {
+                writeln!(out, "{:>3}  {}", number + 1, backup.summary(current))?;
+            }
+            return Ok(());
+        },
+        Command::Restore(number) => {
+            let backups = backup::list(&config)?;
+            let backup = number.checked_sub(1)
+                .and_then(|index| backups.get(index))
+                .ok_or(Error::NoSuchBackup(number))?;
+            let events = backup::restore(&config, backup)?;
+            writeln!(io::stdout().lock(), "restored {} events from {}", events.len(), backup.path.display())?;
+            return Ok(());
+        },
     }
-    // events are sorted first chronologically, then alphabetically by description
-    s.intervals.sort_unstable();
-    s.timed.sort_unstable();
-    s.untimed.sort_unstable();
 
-    if !FILEPATH_BACKUP.is_empty() { fs::copy(FILEPATH, FILEPATH_BACKUP)?; }
+    load(&config, true)?;
+    let (events, mut snapshot) = sync::start(&config.events_file, config.storage()?)?;
+    let mut s = State::default();
+    s.replace_events(events);
+    backup::rotate(&config)?;
+    // without a watch, changes by other programs are still caught before they are overwritten
+    let watcher = Watcher::new(&config.events_file).map_err(|error| s.last_error = Some(error)).ok();
 
     let mut terminal = Terminal::new(
         CrosstermBackend::new(
@@ -50,6 +101,13 @@ fn main() -> Result<(), Error> {
     )?;
     terminal::enable_raw_mode()?;
     terminal.clear()?;
+    // leave the terminal usable if anything goes wrong; edits up to here were autosaved
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let _ = terminal::disable_raw_mode();
+        let _ = crossterm::execute!(io::stdout(), cursor::Show);
+        default_hook(info);
+    }));
 
     // the main loop renders, then handles input
     loop {
@@ -77,14 +135,19 @@ fn main() -> Result<(), Error> {
                     ]
                 )
                 .split(chunks[1]);
+            // both tables have a time column followed by a description
+            let widths = [
+                Constraint::Length(config.col_time_width),
+                Constraint::Min(0),
+            ];
 
             // lay out intervals in the top block
             let table_intervals = Table::new(
-                s.intervals
-                    .iter()
+                s.visible(&s.intervals)
+                    .into_iter()
                     .map(|event| {
                         Row::new(vec![
-                            end_time(&event).unwrap(),
+                            end_time(event, &config).unwrap(),
                             event.description.clone(),
                         ])
                     })
@@ -95,12 +158,9 @@ fn main() -> Result<(), Error> {
             )
             .block(Block::default()
                 .borders(Borders::ALL)
-                .title("Intervals")
+                .title(title("Intervals", &s, &s.intervals))
             )
-            .widths(&[
-                Constraint::Length(COL_TIME_WIDTH),
-                Constraint::Min(0),
-            ])
+            .widths(&widths)
             .highlight_style(Style::default()
                 .add_modifier(Modifier::BOLD)
                 .fg(Color::LightBlue)
@@ -108,12 +168,17 @@ fn main() -> Result<(), Error> {
 
             // lay out timed events in the bottom left block
             let table_timed = Table::new(
-                s.timed
-                    .iter()
+                s.visible(&s.timed)
+                    .into_iter()
                     .map(|event| {
+                        // an override of the current occurrence stands in for the event itself
+                        let description = match event.current_override() {
+                            Some(replacement) => &replacement.description,
+                            None => &event.description,
+                        };
                         Row::new(vec![
-                            start_time(&event).unwrap(),
-                            event.description.clone(),
+                            start_time(event, &config).unwrap(),
+                            description.clone(),
                         ])
                     })
                     .collect::<Vec<Row>>()
@@ -123,26 +188,23 @@ fn main() -> Result<(), Error> {
             )
             .block(Block::default()
                 .borders(Borders::ALL)
-                .title("Timed")
+                .title(title("Timed", &s, &s.timed))
             )
-            .widths(&[
-                Constraint::Length(COL_TIME_WIDTH),
-                Constraint::Min(0),
-            ])
+            .widths(&widths)
             .highlight_style(Style::default()
                 .add_modifier(Modifier::BOLD)
                 .fg(Color::LightBlue)
             );
             
             // lay out untimed events in the bottom right block
-            let list_untimed = List::new(s.untimed
-                .iter()
+            let list_untimed = List::new(s.visible(&s.untimed)
+                .into_iter()
                 .map(|event| ListItem::new(event.description.clone()))
                 .collect::<Vec<ListItem>>()
             )
             .block(Block::default()
                 .borders(Borders::ALL)
-                .title("Untimed")
+                .title(title("Untimed", &s, &s.untimed))
             )
             .highlight_style(Style::default()
                 .add_modifier(Modifier::BOLD)
@@ -151,260 +213,384 @@ fn main() -> Result<(), Error> {
 
             // lay out insert box
             let text = match s.focus {
-                Focus::InputAdd => Paragraph::new(format!("{}{}", ADD_PROMPT, s.buffer.replace('\t', " "))),
+                Focus::InputAdd => Paragraph::new(format!("{}{}", config.add_prompt, s.buffer.replace('\t', " "))),
+                Focus::Search => Paragraph::new(format!("/{}", s.query)),
                 _ => match s.last_error {
+                    Some(Error::Conflict) => Paragraph::new("Warning: the events file changed on disk. [m]erge your changes into it, or [r]eload it and discard yours?"),
+                    Some(Error::DeletionScope) => Paragraph::new("Warning: delete this [o]ccurrence or [a]ll? (irreversible)"),
                     Some(Error::DeletionWarning) => Paragraph::new("Warning: press 'd' again to delete (irreversible)."),
+                    Some(Error::FileCorrupt(ref error)) => Paragraph::new(format!("Error: could not reload events: {}.", error)),
                     Some(Error::InvalidIso) => Paragraph::new("Error: the string was not properly formatted."),
                     Some(Error::InvalidRecord) => Paragraph::new("Error: input: \"[iso string]\\t[description]\""),
+                    Some(Error::InvalidRule) => Paragraph::new("Error: the recurrence rule was not understood."),
                     Some(Error::InvalidTime) => Paragraph::new("Error: the time entered was invalid or not specific enough."),
+                    Some(Error::Io(ref error)) => Paragraph::new(format!("Error: could not save events: {}.", error)),
+                    Some(Error::Iso(error)) => Paragraph::new(format!("Error: {}.", error)),
                     Some(Error::NoInfo) => Paragraph::new("The event contained no information, so was not added."),
+                    Some(Error::Reloaded) => Paragraph::new("Reloaded the events, which changed on disk."),
+                    Some(Error::Restored(count)) => Paragraph::new(format!("Restored {} events; the previous ones were backed up first.", count)),
+                    Some(Error::Watch(ref error)) => Paragraph::new(format!("Warning: cannot watch the events file for changes: {}.", error)),
+                    Some(Error::YankScope) => Paragraph::new("Warning: yank this [o]ccurrence or [a]ll into buffer?"),
                     Some(Error::YankWarning) => Paragraph::new("Warning: press 'y' again to yank selected event into buffer."),
+                    Some(Error::Zone(ref error)) => Paragraph::new(format!("Error: {}.", error)),
+                    _ if !s.query.is_empty() => Paragraph::new(format!("/{}", s.query)),
                     _ => Paragraph::new(""),
                 },
-            }.block(Block::default().borders(Borders::ALL));
+            };
+            // while typing, preview the dates the buffer resolves to, or say how a search is taken
+            let title = match s.focus {
+                Focus::InputAdd => s.event_from_buffer().ok().and_then(|event| preview(&event, &config)),
+                Focus::Search => s.query.parse::<Filter>().err().map(|error| format!("{}; matching descriptions only", error)),
+                _ if !s.query.is_empty() => Some("'n'/'N' jumps between matches, 'Esc' shows everything".to_string()),
+                _ => None,
+            };
+            let text = text.block(Block::default().borders(Borders::ALL).title(title.unwrap_or_default()));
 
             term.render_stateful_widget(table_intervals, chunks[0], &mut s.intervals_state);
             term.render_stateful_widget(table_timed, chunks_bottom[0], &mut s.timed_state);
             term.render_stateful_widget(list_untimed, chunks_bottom[1], &mut s.untimed_state);
             term.render_widget(text, chunks[2]);
+
+            // the backup picker covers the bottom panes while open
+            if let Focus::Backups = s.focus {
+                let current = s.events().len();
+                let list_backups = List::new(
+                    s.backups
+                        .iter()
+                        .map(|backup| ListItem::new(backup.summary(current)))
+                        .collect::<Vec<ListItem>>()
+                )
+                .block(Block::default()
+                    .borders(Borders::ALL)
+                    .title(if s.backups.is_empty() { "Backups: none yet" } else { "Backups: 'Enter' restores, 'Esc' closes" })
+                )
+                .highlight_style(Style::default()
+                    .add_modifier(Modifier::BOLD)
+                    .fg(Color::LightBlue)
+                );
+                term.render_widget(Clear, chunks[1]);
+                term.render_stateful_widget(list_backups, chunks[1], &mut s.backups_state);
+            }
         })?;
 
         // this is the event-handling half of the main loop; keybindings are matched individually
         // in the match arm for each "Focus" (insert, or whichever pane is selected, if any)
-        match s.focus {
-            Focus::InputAdd => {
-                let len: u16 = if s.cursor_offset > u16::MAX.into() {
-                    u16::MAX
-                } else {
-                    s.cursor_offset as u16
-                };
-                terminal.set_cursor(ADD_PROMPT_LEN + len + 1, terminal.size()?.height - 2)?;
-                terminal.show_cursor()?;
+        if let Focus::InputAdd = s.focus {
+            let len: u16 = if s.cursor_offset > u16::MAX.into() {
+                u16::MAX
+            } else {
+                s.cursor_offset as u16
+            };
+            terminal.set_cursor(config.add_prompt_len() + len + 1, terminal.size()?.height - 2)?;
+            terminal.show_cursor()?;
+        }
+        if let Focus::Search = s.focus {
+            let len = s.query.chars().count().min(u16::MAX as usize - 2) as u16;
+            terminal.set_cursor(len + 2, terminal.size()?.height - 2)?;
+            terminal.show_cursor()?;
+        }
 
-                match event::read()? {
-                    event::Event::Key(keycode) => match keycode.code {
-                        KeyCode::Backspace => if s.cursor_offset > 0 {
-                            s.cursor_offset -= 1;
-                            s.buffer.remove(s.cursor_offset);
-                        },
-                        KeyCode::Char(c) => {
-                            s.buffer.insert(s.cursor_offset, c);
-                            s.cursor_offset += 1;
-                        },
-                        KeyCode::Delete => if s.cursor_offset < s.buffer.len() {
-                            s.buffer.remove(s.cursor_offset);
-                        },
-                        KeyCode::Down => s.cursor_end(),
-                        KeyCode::Enter => {
-                            match s.add_event_from_buffer() {
-                                err @ Err(Error::InvalidIso)
-                                    | err @ Err(Error::InvalidRecord)
-                                    | err @ Err(Error::InvalidTime)
-                                    | err @ Err(Error::NoInfo)
-                                    => s.last_error = Some(err.unwrap_err()),
-                                _ => s.last_error = None,
-                            }
+        // without input, wake up every TICK to roll repeating events past the current time
+        if !event::poll(TICK)? {
+            s.advance_repeating();
+            follow(&watcher, &mut snapshot, &mut s);
+            autosave(&mut snapshot, &mut s);
+            continue;
+        }
+        let keycode = match event::read()? {
+            event::Event::Key(keycode) => keycode.code,
+            _ => continue,
+        };
+
+        match s.focus {
+            Focus::Backups => match keycode {
+                KeyCode::Char('j') => s.scroll_down(),
+                KeyCode::Char('k') => s.scroll_up(),
+                KeyCode::Enter => if let Some(index) = s.backups_state.selected() {
+                    let restored = backup::restore(&config, &s.backups[index])
+                        .and_then(|events| sync::reload(&mut snapshot, &mut s).map(|()| events.len()));
+                    match restored {
+                        Ok(count) => {
+                            s.last_error = Some(Error::Restored(count));
                             s.focus(Focus::None);
                         },
-                        KeyCode::Esc => s.focus(Focus::None),
-                        KeyCode::Left => s.cursor_left(),
-                        KeyCode::Right => s.cursor_right(),
-                        KeyCode::Tab => {
-                            s.buffer.insert(s.cursor_offset, '\t');
-                            s.cursor_offset += 1;
-                        },
-                        KeyCode::Up => s.cursor_beginning(),
-                        _ => {},
-                    },
-                    _ => {},
-                }
+                        Err(error) => s.last_error = Some(error),
+                    }
+                },
+                KeyCode::Esc => s.focus(Focus::None),
+                _ => {},
             },
-            Focus::Intervals => match event::read()? {
-                event::Event::Key(keycode) => match keycode.code {
-                    KeyCode::Char('d') => s.delete_selected(),
-                    KeyCode::Char('h') => s.focus(Focus::Timed),
-                    KeyCode::Char('i') => {
-                        s.focus(Focus::InputAdd);
-                        terminal.show_cursor()?;
-                        terminal.set_cursor(ADD_PROMPT_LEN, 0)?;
-                    },
-                    KeyCode::Char('j') => s.scroll_down(),
-                    KeyCode::Char('k') => s.scroll_up(),
-                    KeyCode::Char('l') => s.focus(Focus::Untimed),
-                    KeyCode::Char('q') => break,
-                    KeyCode::Char('y') => s.yank_selected(),
-                    KeyCode::Esc => match s.last_error {
-                        Some(Error::DeletionWarning) => s.last_error = None,
-                        _ => s.focus(Focus::None),
-                    },
-                    _ => {},
+            Focus::InputAdd => match keycode {
+                KeyCode::Backspace if s.cursor_offset > 0 => {
+                    s.cursor_offset -= 1;
+                    s.buffer.remove(s.cursor_offset);
                 },
+                KeyCode::Char(c) => {
+                    s.buffer.insert(s.cursor_offset, c);
+                    s.cursor_offset += 1;
+                },
+                KeyCode::Delete if s.cursor_offset < s.buffer.len() => {
+                    s.buffer.remove(s.cursor_offset);
+                },
+                KeyCode::Down => s.cursor_end(),
+                KeyCode::Enter => {
+                    match s.add_event_from_buffer() {
+                        err @ Err(Error::InvalidIso)
+                            | err @ Err(Error::InvalidRecord)
+                            | err @ Err(Error::InvalidRule)
+                            | err @ Err(Error::InvalidTime)
+                            | err @ Err(Error::Iso(_))
+                            | err @ Err(Error::NoInfo)
+                            | err @ Err(Error::Zone(_))
+                            => s.last_error = Some(err.unwrap_err()),
+                        _ => s.last_error = None,
+                    }
+                    s.focus(Focus::None);
+                },
+                KeyCode::Esc => s.focus(Focus::None),
+                KeyCode::Left => s.cursor_left(),
+                KeyCode::Right => s.cursor_right(),
+                KeyCode::Tab => {
+                    s.buffer.insert(s.cursor_offset, '\t');
+                    s.cursor_offset += 1;
+                },
+                KeyCode::Up => s.cursor_beginning(),
                 _ => {},
             },
-            Focus::Timed => match event::read()? {
-                event::Event::Key(keycode) => match keycode.code {
-                    KeyCode::Char('d') => s.delete_selected(),
-                    KeyCode::Char('g') => s.focus(Focus::Intervals),
-                    KeyCode::Char('i') => {
-                        s.focus(Focus::InputAdd);
-                        terminal.set_cursor(ADD_PROMPT_LEN, 0)?;
-                    },
-                    KeyCode::Char('j') => s.scroll_down(),
-                    KeyCode::Char('k') => s.scroll_up(),
-                    KeyCode::Char('l') => s.focus(Focus::Untimed),
-                    KeyCode::Char('q') => break,
-                    KeyCode::Char('y') => s.yank_selected(),
-                    KeyCode::Esc => match s.last_error {
-                        Some(Error::DeletionWarning) => s.last_error = None,
-                        _ => s.focus(Focus::None),
-                    },
-                    _ => {},
+            // the panes follow the search as it is typed
+            Focus::Search => match keycode {
+                KeyCode::Backspace => {
+                    let mut query = s.query.clone();
+                    query.pop();
+                    s.set_query(query);
+                },
+                KeyCode::Char(c) => s.set_query(format!("{}{}", s.query, c)),
+                KeyCode::Enter => {
+                    s.focus(Focus::None);
+                    if !s.query.is_empty() { s.jump(true); }
+                },
+                KeyCode::Esc => {
+                    s.set_query(String::new());
+                    s.focus(Focus::None);
                 },
                 _ => {},
             },
-            Focus::Untimed => match event::read()? {
-                event::Event::Key(keycode) => match keycode.code {
-                    KeyCode::Char('d') => s.delete_selected(),
-                    KeyCode::Char('g') => s.focus(Focus::Intervals),
-                    KeyCode::Char('h') => s.focus(Focus::Timed),
-                    KeyCode::Char('i') => {
-                        s.focus(Focus::InputAdd);
-                        terminal.set_cursor(ADD_PROMPT_LEN, 0)?;
-                    },
-                    KeyCode::Char('j') => s.scroll_down(),
-                    KeyCode::Char('k') => s.scroll_up(),
-                    KeyCode::Char('q') => break,
-                    KeyCode::Char('y') => s.yank_selected(),
-                    KeyCode::Esc => match s.last_error {
-                        Some(Error::DeletionWarning) => s.last_error = None,
-                        _ => s.focus(Focus::None),
-                    },
-                    _ => {},
+            Focus::Intervals => match keycode {
+                KeyCode::Char('b') => open_backups(&config, &mut s),
+                KeyCode::Char('d') => s.delete_selected(),
+                KeyCode::Char('h') => s.focus(Focus::Timed),
+                KeyCode::Char('i') => {
+                    s.focus(Focus::InputAdd);
+                    terminal.show_cursor()?;
+                    terminal.set_cursor(config.add_prompt_len(), 0)?;
+                },
+                KeyCode::Char('j') => s.scroll_down(),
+                KeyCode::Char('k') => s.scroll_up(),
+                KeyCode::Char('l') => s.focus(Focus::Untimed),
+                KeyCode::Char('m') => resolve(&mut snapshot, &mut s, sync::merge),
+                KeyCode::Char('n') => s.jump(true),
+                KeyCode::Char('q') => break,
+                KeyCode::Char('r') => resolve(&mut snapshot, &mut s, sync::reload),
+                KeyCode::Char('y') => s.yank_selected(),
+                KeyCode::Char('N') => s.jump(false),
+                KeyCode::Char('/') => search(&mut s),
+                KeyCode::Esc => match s.last_error {
+                    Some(Error::DeletionWarning) => s.last_error = None,
+                    _ if !s.query.is_empty() => s.set_query(String::new()),
+                    _ => s.focus(Focus::None),
                 },
                 _ => {},
             },
-            Focus::None => match event::read()? {
-                event::Event::Key(keycode) => match keycode.code {
-                    KeyCode::Char('g') => s.focus(Focus::Intervals),
-                    KeyCode::Char('h') => s.focus(Focus::Timed),
-                    KeyCode::Char('i') => {
-                        s.focus(Focus::InputAdd);
-                        terminal.set_cursor(ADD_PROMPT_LEN, 0)?;
-                    },
-                    KeyCode::Char('l') => s.focus(Focus::Untimed),
-                    KeyCode::Char('q') => break,
-                    KeyCode::Esc => s.last_error = None,
-                    _ => {},
+            Focus::Timed => match keycode {
+                KeyCode::Char('a') => s.choose_scope(Scope::All),
+                KeyCode::Char('b') => open_backups(&config, &mut s),
+                KeyCode::Char('d') => s.delete_selected(),
+                KeyCode::Char('g') => s.focus(Focus::Intervals),
+                KeyCode::Char('i') => {
+                    s.focus(Focus::InputAdd);
+                    terminal.set_cursor(config.add_prompt_len(), 0)?;
+                },
+                KeyCode::Char('j') => s.scroll_down(),
+                KeyCode::Char('k') => s.scroll_up(),
+                KeyCode::Char('l') => s.focus(Focus::Untimed),
+                KeyCode::Char('m') => resolve(&mut snapshot, &mut s, sync::merge),
+                KeyCode::Char('n') => s.jump(true),
+                KeyCode::Char('o') => s.choose_scope(Scope::Occurrence),
+                KeyCode::Char('q') => break,
+                KeyCode::Char('r') => resolve(&mut snapshot, &mut s, sync::reload),
+                KeyCode::Char('y') => s.yank_selected(),
+                KeyCode::Char('N') => s.jump(false),
+                KeyCode::Char('/') => search(&mut s),
+                KeyCode::Esc => match s.last_error {
+                    Some(Error::DeletionWarning)
+                        | Some(Error::DeletionScope)
+                        | Some(Error::YankScope)
+                    => s.last_error = None,
+                    _ if !s.query.is_empty() => s.set_query(String::new()),
+                    _ => s.focus(Focus::None),
+                },
+                _ => {},
+            },
+            Focus::Untimed => match keycode {
+                KeyCode::Char('b') => open_backups(&config, &mut s),
+                KeyCode::Char('d') => s.delete_selected(),
+                KeyCode::Char('g') => s.focus(Focus::Intervals),
+                KeyCode::Char('h') => s.focus(Focus::Timed),
+                KeyCode::Char('i') => {
+                    s.focus(Focus::InputAdd);
+                    terminal.set_cursor(config.add_prompt_len(), 0)?;
+                },
+                KeyCode::Char('j') => s.scroll_down(),
+                KeyCode::Char('k') => s.scroll_up(),
+                KeyCode::Char('m') => resolve(&mut snapshot, &mut s, sync::merge),
+                KeyCode::Char('n') => s.jump(true),
+                KeyCode::Char('q') => break,
+                KeyCode::Char('r') => resolve(&mut snapshot, &mut s, sync::reload),
+                KeyCode::Char('y') => s.yank_selected(),
+                KeyCode::Char('N') => s.jump(false),
+                KeyCode::Char('/') => search(&mut s),
+                KeyCode::Esc => match s.last_error {
+                    Some(Error::DeletionWarning) => s.last_error = None,
+                    _ if !s.query.is_empty() => s.set_query(String::new()),
+                    _ => s.focus(Focus::None),
+                },
+                _ => {},
+            },
+            Focus::None => match keycode {
+                KeyCode::Char('b') => open_backups(&config, &mut s),
+                KeyCode::Char('g') => s.focus(Focus::Intervals),
+                KeyCode::Char('h') => s.focus(Focus::Timed),
+                KeyCode::Char('i') => {
+                    s.focus(Focus::InputAdd);
+                    terminal.set_cursor(config.add_prompt_len(), 0)?;
+                },
+                KeyCode::Char('l') => s.focus(Focus::Untimed),
+                KeyCode::Char('m') => resolve(&mut snapshot, &mut s, sync::merge),
+                KeyCode::Char('n') => s.jump(true),
+                KeyCode::Char('q') => break,
+                KeyCode::Char('r') => resolve(&mut snapshot, &mut s, sync::reload),
+                KeyCode::Char('N') => s.jump(false),
+                KeyCode::Char('/') => search(&mut s),
+                KeyCode::Esc => {
+                    s.last_error = None;
+                    s.set_query(String::new());
                 },
                 _ => {},
             },
         }
+        follow(&watcher, &mut snapshot, &mut s);
+        autosave(&mut snapshot, &mut s);
     }
 
     terminal::disable_raw_mode()?;
     terminal.clear()?;
     terminal.set_cursor(0, 0)?;
-    serialize(s.take_all())
+    // quitting never discards changes, so a conflict still pending is merged
+    if !s.dirty { return Ok(()); }
+    match sync::save(&mut snapshot, &s) {
+        Err(Error::Conflict) => sync::merge(&mut snapshot, &mut s),
+        result => result,
+    }
 }
 
-/// Attempts to parse a chrono DateTime from the given string using the Local timezone.
-fn datetime_from_iso(string: &str) -> Result<DateTime<Local>, Error> {
-    let mut tokens = string.split(&['-', 'T', ':'][..]);
-    let date = match (tokens.next(), tokens.next(), tokens.next()) {
-        (Some(year), Some(month), Some(day)) => {
-            let year = match year.parse::<i32>() {
-                Ok(num) => num,
-                Err(_) => return Err(Error::InvalidIso),
-            };
-            let month = match month.parse::<u32>() {
-                Ok(num) => num,
-                Err(_) => return Err(Error::InvalidIso),
-            };
-            let day = match day.parse::<u32>() {
-                Ok(num) => num,
-                Err(_) => return Err(Error::InvalidIso),
-            };
-            Local.ymd_opt(year, month, day)
-        },
-        _ => return Err(Error::InvalidIso),
-    };
-    let datetime = match date {
-        LocalResult::Single(date) => date.and_hms_opt(
-            match tokens.next() {
-                Some(hour) => match hour.parse::<u32>() {
-                    Ok(num) => num,
-                    Err(_) => return Err(Error::InvalidIso),
-                },
-                _ => 0,
-            },
-            match tokens.next() {
-                Some(minute) => match minute.parse::<u32>() {
-                    Ok(num) => num,
-                    Err(_) => return Err(Error::InvalidIso),
-                },
-                _ => 0,
-            },
-            match tokens.next() {
-                Some(second) => match second.parse::<u32>() {
-                    Ok(num) => num,
-                    Err(_) => return Err(Error::InvalidIso),
-                },
-                _ => 0,
-            }
-        ),
-        _ => return Err(Error::InvalidTime),
-    };
-    match datetime {
-        Some(d) => Ok(d),
-        None => Err(Error::InvalidTime),
+/// Saves the events if they changed, showing any failure in the interface. The events stay marked
+/// as changed until a save succeeds, so that it is retried; if the file changed on disk in the
+/// meantime, the user is asked whether to merge or reload.
+fn autosave(snapshot: &mut Snapshot, s: &mut State) {
+    if !s.dirty { return; }
+    match sync::save(snapshot, s) {
+        Ok(()) => s.dirty = false,
+        Err(error) => s.last_error = Some(error),
     }
 }
 
 /// Formats a string from an existing event so that events yanked into buffer have enough
 /// information to be valid.
-fn event_to_record(event: Event) -> String {
-    const ISO_FULL: &str = "%FT%R";
-    match (event.start, event.interval) {
+fn event_to_record(event: &Event) -> String {
+    // an interval entered as a duration is written back that way
+    let (start, end) = match (event.start, &event.interval, event.span) {
+        (Some(start), Interval::RepDefinite { end, .. }, span)
+            | (Some(start), Interval::RepIndefinite(end), span)
+            | (Some(start), Interval::Standard(end), span)
+        => match span {
+            Some(Span::After(duration)) => (timestamp(start, event.zone), duration.to_string()),
+            Some(Span::Before(duration)) => (duration.to_string(), timestamp(*end, event.zone)),
+            None => (timestamp(start, event.zone), timestamp(*end, event.zone)),
+        },
+        (Some(start), Interval::None, _) => (timestamp(start, event.zone), String::new()),
+        (None, _, _) => (String::new(), String::new()),
+    };
+    // the rule is only spelled out if it would not be inferred again from the start and end
+    let rule = match (event.start, &event.interval, &event.rule) {
+        (Some(start), Interval::RepDefinite { end, .. }, Some(rule))
+            | (Some(start), Interval::RepIndefinite(end), Some(rule))
+        => {
+            let (start, end) = (start.with_timezone(&event.zone), end.with_timezone(&event.zone));
+            let (start, end) = match event.span {
+                Some(Span::After(duration)) => (start, duration.add_to(start).unwrap_or(end)),
+                Some(Span::Before(duration)) => (duration.subtract_from(end).unwrap_or(start), end),
+                None => (start, end),
+            };
+            if Recurrence::infer(start, end).as_ref() != Some(rule) {
+                format!(";{}", rule)
+            } else {
+                String::new()
+            }
+        },
+        _ => String::new(),
+    };
+    match (event.start, &event.interval) {
         (None, _) => format!(
             "\t{}",
             event.description
         ),
-        (_, Interval::RepDefinite { occurrences, end }) => format!(
-            "R{}/{}/{}\t{}",
+        (_, Interval::RepDefinite { occurrences, .. }) => format!(
+            "R{}/{}/{}{}\t{}",
             occurrences,
-            event.start.unwrap().format(ISO_FULL),
-            end.format(ISO_FULL),
+            start,
+            end,
+            rule,
             event.description
         ),
-        (_, Interval::RepIndefinite(end)) => format!(
-            "R/{}/{}\t{}",
-            event.start.unwrap().format(ISO_FULL),
-            end.format(ISO_FULL),
+        (_, Interval::RepIndefinite(_)) => format!(
+            "R/{}/{}{}\t{}",
+            start,
+            end,
+            rule,
             event.description
         ),
-        (_, Interval::Standard(end)) => format!(
+        (_, Interval::Standard(_)) => format!(
             "{}/{}\t{}",
-            event.start.unwrap().format(ISO_FULL),
-            end.format(ISO_FULL),
+            start,
+            end,
             event.description
         ),
         (_, Interval::None) => format!(
             "{}\t{}",
-            event.start.unwrap().format(ISO_FULL),
+            start,
             event.description
         ),
     }
 }
 
-/// Attempts to deserialize a vector of Events from FILEPATH.
-fn deserialize() -> Result<Vec<Event>, Error> {
-    let file = fs::read_to_string(FILEPATH)?;
-    Ok(serde_json::from_str(&file)?)
+/// Formats a string describing only the current occurrence of a repeating event, as it would be
+/// entered as a single event.
+fn occurrence_to_record(event: &Event) -> String {
+    match (event.current_override(), event.start) {
+        (Some(Override { start, description, .. }), _) => format!("{}\t{}", timestamp(*start, event.zone), description),
+        (None, Some(start)) => format!("{}\t{}", timestamp(start, event.zone), event.description),
+        (None, None) => format!("\t{}", event.description),
+    }
+}
+
+/// Attempts to deserialize a vector of Events from the given JSON file, such as a backup.
+fn deserialize(path: &Path) -> Result<Vec<Event>, Error> {
+    Ok(JsonFile::new(path).load()?)
 }
 
 /// Returns a string representing the end-time of an interval. Returns None for events without an
 /// end-time.
-fn end_time(event: &Event) -> Option<String> {
+fn end_time(event: &Event, config: &Config) -> Option<String> {
     match event.interval {
         Interval::RepDefinite { end, .. }
             | Interval::RepIndefinite(end)
@@ -412,31 +598,156 @@ fn end_time(event: &Event) -> Option<String> {
         => {
             let time = end.time();
             if time.hour() == 0 && time.minute() == 0 {
-                Some(end.format(YMD).to_string())
+                Some(end.format(&config.date_format).to_string())
             } else {
-                Some(end.format(YMDHM).to_string())
+                Some(end.format(&config.datetime_format).to_string())
             }
         },
         Interval::None => None,
     }
 }
 
-/// Attempts to serialize a vector of Events into FILEPATH.
-fn serialize(events: Vec<Event>) -> Result<(), Error> {
-    Ok(fs::write(FILEPATH, &serde_json::to_vec(&events)?)?)
+/// Reloads the events if another program changed the events file, and says so.
+fn follow(watcher: &Option<Watcher>, snapshot: &mut Snapshot, s: &mut State) {
+    if !watcher.as_ref().is_some_and(Watcher::changed) { return; }
+    match sync::follow(snapshot, s) {
+        Ok(true) => s.last_error = Some(Error::Reloaded),
+        Ok(false) => {},
+        Err(error) => s.last_error = Some(error),
+    }
+}
+
+/// Makes sure the events file can be read, creating an empty one on first launch, upgrading one
+/// from an older version and, if `interactive`, asking what to do if it is corrupt. Otherwise a
+/// corrupt file is reported as it is, so that scripts are not left waiting on an answer. An
+/// upgraded file is first copied aside as it was, for instance to `events.json.v0`. A database
+/// needs none of this, as it is created when opened.
+fn load(config: &Config, interactive: bool) -> Result<(), Error> {
+    if config.storage == Backend::Sqlite { return Ok(()); }
+    let path = &config.events_file;
+    match JsonFile::new(path).load_versioned().map_err(Error::from) {
+        Err(Error::FileMissing) => {
+            if let Some(parent) = path.parent() { fs::create_dir_all(parent)?; }
+            serialize(path, &[])
+        },
+        Ok((events, version)) if version < json::VERSION => {
+            let _lock = sync::lock(path)?;
+            fs::copy(path, path.with_extension(format!("json.v{}", version)))?;
+            serialize(path, &events.iter().collect::<Vec<&Event>>())
+        },
+        Err(Error::FileEmpty) => Ok(()),
+        Err(Error::FileCorrupt(error)) if interactive => recover(config, Error::FileCorrupt(error)),
+        result => result.map(|_| ()),
+    }
 }
 
-/// Returns a string representing the start-time of an interval. Returns None for untimed events.
-fn start_time(event: &Event) -> Option<String> {
-    match event.start {
+/// Lists the backups in the picker, or shows why they could not be listed.
+fn open_backups(config: &Config, s: &mut State) {
+    match backup::list(config) {
+        Ok(backups) => {
+            s.backups = backups;
+            s.focus(Focus::Backups);
+        },
+        Err(error) => s.last_error = Some(error),
+    }
+}
+
+/// Returns a string describing when an event takes place, for previewing the insertion buffer.
+/// Returns None for untimed events.
+fn preview(event: &Event, config: &Config) -> Option<String> {
+    let start = event.start?.format("%a %F %R");
+    let when = match (&event.interval, end_time(event, config)) {
+        (Interval::None, _) | (_, None) => start.to_string(),
+        (Interval::Standard(_), Some(end)) => format!("{} to {}", start, end),
+        (_, Some(end)) => format!("{} to {}, repeating", start, end),
+    };
+    // times are shown in the viewer's timezone, so say where the event was entered
+    Some(match event.zone {
+        Zone::Floating => when,
+        zone => format!("{} (from {})", when, zone),
+    })
+}
+
+/// Asks on the command line whether to restore a corrupt events file from the backup, to set it
+/// aside and start over, or to quit without touching it. Returns the error on quitting.
+fn recover(config: &Config, error: Error) -> Result<(), Error> {
+    let path = &config.events_file;
+    let backup = backup::list(config)?.into_iter().find_map(|backup| deserialize(&backup.path).ok());
+    let aside = path.with_extension("json.corrupt");
+    eprintln!("{} could not be read: {}", path.display(), error);
+    if backup.is_some() { eprintln!("  [r]estore the events from the most recent readable backup"); }
+    eprintln!("  [s]tart over with no events, keeping the unreadable file as {}", aside.display());
+    eprintln!("  [q]uit without changing anything");
+    loop {
+        eprint!("> ");
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer)? == 0 { return Err(error); }
+        match (answer.trim(), &backup) {
+            ("r", Some(_)) => {
+                let events = backup.unwrap_or_default();
+                fs::rename(path, &aside)?;
+                return serialize(path, &events.iter().collect::<Vec<&Event>>());
+            },
+            ("s", _) => {
+                fs::rename(path, &aside)?;
+                return serialize(path, &[]);
+            },
+            ("q", _) => return Err(error),
+            _ => {},
+        }
+    }
+}
+
+/// Settles a conflict with the events file, if one is pending, by merging or reloading.
+fn resolve(snapshot: &mut Snapshot, s: &mut State, settle: fn(&mut Snapshot, &mut State) -> Result<(), Error>) {
+    if !matches!(s.last_error, Some(Error::Conflict)) { return; }
+    s.last_error = settle(snapshot, s).err();
+}
+
+/// Opens the search prompt, which starts from the current search so that it can be refined.
+fn search(s: &mut State) {
+    s.last_error = None;
+    s.focus(Focus::Search);
+}
+
+/// Attempts to serialize events into the given JSON file.
+fn serialize(path: &Path, events: &[&Event]) -> Result<(), Error> {
+    Ok(JsonFile::new(path).save(events)?)
+}
+
+/// Returns a string representing the start-time of an interval, taking an override of the
+/// current occurrence into account. Returns None for untimed events.
+fn start_time(event: &Event, config: &Config) -> Option<String> {
+    match event.current_override().map(|replacement| replacement.start).or(event.start) {
         Some(datetime) => {
             let time = datetime.time();
             if time.hour() == 0 && time.minute() == 0 {
-                Some(datetime.format(YMD).to_string())
+                Some(datetime.format(&config.date_format).to_string())
             } else {
-                Some(datetime.format(YMDHM).to_string())
+                Some(datetime.format(&config.datetime_format).to_string())
             }
         },
         None => None,
     }
 }
+
+/// Returns the title of a pane, which counts the matches out of all its events while searching.
+fn title(name: &str, s: &State, pane: &[Event]) -> String {
+    if s.query.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}: {} of {}", name, s.visible(pane).len(), pane.len())
+    }
+}
+
+/// Formats a time as it would be entered in the given timezone: floating times bare, times at a
+/// fixed offset with that offset, and times in a named timezone with its name in brackets.
+fn timestamp(time: DateTime<Local>, zone: Zone) -> String {
+    const ISO_FULL: &str = "%FT%R";
+    let time = time.with_timezone(&zone);
+    match zone {
+        Zone::Floating => time.format(ISO_FULL).to_string(),
+        Zone::Fixed(_) => time.format("%FT%R%:z").to_string(),
+        Zone::Named(tz) => format!("{}[{}]", time.format(ISO_FULL), tz.name()),
+    }
+}
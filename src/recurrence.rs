@@ -0,0 +1,487 @@
+//! Contains the calendar-aware recurrence rules used by repeating events.
+
+use chrono::{
+    DateTime,
+    Datelike,
+    Duration,
+    NaiveDate,
+    NaiveDateTime,
+    TimeZone,
+    Weekday,
+};
+use serde::{Deserialize, Serialize};
+use std::{convert::TryFrom, fmt, str::FromStr};
+
+/// How many periods in a row may produce no occurrence before a rule is considered exhausted. This
+/// guards against rules that can never match.
+const MAX_EMPTY_PERIODS: i64 = 1000;
+
+/// The calendar unit by which a rule repeats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// A weekday with an optional ordinal, as in the BYDAY part of an RFC 5545 RRULE: "TU" is every
+/// Tuesday, "2TU" the second Tuesday and "-1FR" the last Friday of the month.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WeekdayNum {
+    /// Counts from the start of the month if positive, from the end if negative. Zero matches
+    /// every such weekday.
+    pub ordinal: i8,
+    pub weekday: Weekday,
+}
+
+/// A recurrence rule in the spirit of RFC 5545 RRULEs. Occurrences are computed relative to an
+/// anchor, which is always an occurrence itself (usually the event's start); the time of day is
+/// taken from the anchor.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    /// Repeat every `interval` periods.
+    pub interval: u32,
+    /// Weekdays on which to occur. Ordinals are only meaningful for monthly and yearly rules.
+    pub by_day: Vec<WeekdayNum>,
+    /// Day of the month on which to occur, clamped to the end of shorter months. Negative values
+    /// count from the end of the month.
+    pub by_month_day: Option<i8>,
+    /// Month of the year on which to occur. Only meaningful for yearly rules.
+    pub by_month: Option<u32>,
+}
+
+/// Returned when a string cannot be parsed as a Recurrence.
+#[derive(Debug, PartialEq)]
+pub struct ParseRuleError(pub String);
+
+impl Recurrence {
+    /// Returns the first occurrence at or after `time`, given an anchor occurrence. Returns None if
+    /// the rule can never occur.
+    pub fn at_or_after<Tz: TimeZone>(&self, anchor: DateTime<Tz>, time: DateTime<Tz>) -> Option<DateTime<Tz>>
+    where
+        Tz::Offset: Copy,
+    {
+        let mut period = (self.period_of(&anchor, &time) - 1).max(0);
+        let mut empty = 0;
+        while empty < MAX_EMPTY_PERIODS {
+            // periods past the last representable date end the rule
+            let candidates = self.candidates(anchor, period)?;
+            if candidates.is_empty() { empty += 1; } else { empty = 0; }
+            if let Some(found) = candidates.into_iter().find(|&candidate| candidate >= time) {
+                return Some(found);
+            }
+            period += 1;
+        }
+        None
+    }
+
+    /// Returns the first occurrence strictly after `time`, given an anchor occurrence.
+    pub fn after<Tz: TimeZone>(&self, anchor: DateTime<Tz>, time: DateTime<Tz>) -> Option<DateTime<Tz>>
+    where
+        Tz::Offset: Copy,
+    {
+        self.at_or_after(anchor, time + Duration::seconds(1))
+    }
+
+    /// Attempts to describe the repetition from `start` to `end` in calendar terms, so that
+    /// "R/2021-01-31/2021-02-28" repeats on the last day of every month and
+    /// "R/1970-01-01/1971-01-01" on every January 1st. Returns None if the two times do not fall
+    /// at the same time of day, or no whole number of calendar units separates them.
+    pub fn infer<Tz: TimeZone>(start: DateTime<Tz>, end: DateTime<Tz>) -> Option<Recurrence> {
+        if end <= start || start.time() != end.time() { return None; }
+        let (from, to) = (start.date().naive_local(), end.date().naive_local());
+        let months = (to.year() - from.year()) * 12 + to.month() as i32 - from.month() as i32;
+        let mut rule = Recurrence {
+            frequency: Frequency::Daily,
+            interval: 1,
+            by_day: Vec::new(),
+            by_month_day: None,
+            by_month: None,
+        };
+        if months > 0 && months % 12 == 0 && from.month() == to.month() && from.day() == to.day() {
+            rule.frequency = Frequency::Yearly;
+            rule.interval = (months / 12) as u32;
+            rule.by_month = Some(from.month());
+            rule.by_month_day = Some(from.day() as i8);
+        } else if months > 0 && from.day() == to.day() {
+            rule.frequency = Frequency::Monthly;
+            rule.interval = months as u32;
+            rule.by_month_day = Some(from.day() as i8);
+        } else if months > 0 && is_last_of_month(from) && is_last_of_month(to) {
+            rule.frequency = Frequency::Monthly;
+            rule.interval = months as u32;
+            rule.by_month_day = Some(-1);
+        } else if months > 0 && is_last_of_month(to) && from.day() > to.day() {
+            // the end was clamped to a shorter month
+            rule.frequency = Frequency::Monthly;
+            rule.interval = months as u32;
+            rule.by_month_day = Some(from.day() as i8);
+        } else {
+            let days = (to - from).num_days();
+            if days % 7 == 0 {
+                rule.frequency = Frequency::Weekly;
+                rule.interval = (days / 7) as u32;
+            } else {
+                rule.interval = days as u32;
+            }
+        }
+        Some(rule)
+    }
+
+    /// Returns the sorted occurrences within the `period`th period after the anchor's, or None if
+    /// that period lies beyond the dates that can be represented.
+    fn candidates<Tz: TimeZone>(&self, anchor: DateTime<Tz>, period: i64) -> Option<Vec<DateTime<Tz>>>
+    where
+        Tz::Offset: Copy,
+    {
+        let date = anchor.date().naive_local();
+        let step = period.checked_mul(self.interval as i64)?;
+        let days: Vec<NaiveDate> = match self.frequency {
+            Frequency::Daily => {
+                let day = add_days(date, step)?;
+                if self.by_day.is_empty() || self.by_day.iter().any(|by| by.weekday == day.weekday()) {
+                    vec![day]
+                } else {
+                    Vec::new()
+                }
+            },
+            Frequency::Weekly => {
+                let monday = add_days(date - Duration::days(date.weekday().num_days_from_monday() as i64), step.checked_mul(7)?)?;
+                if self.by_day.is_empty() {
+                    vec![monday + Duration::days(date.weekday().num_days_from_monday() as i64)]
+                } else {
+                    let mut days: Vec<NaiveDate> = self.by_day
+                        .iter()
+                        .map(|by| monday + Duration::days(by.weekday.num_days_from_monday() as i64))
+                        .collect();
+                    days.sort_unstable();
+                    days.dedup();
+                    days
+                }
+            },
+            Frequency::Monthly => {
+                let months = (date.year() as i64 * 12 + date.month0() as i64).checked_add(step)?;
+                let year = i32::try_from(months.div_euclid(12)).ok()?;
+                NaiveDate::from_ymd_opt(year, 1, 1)?;
+                self.days_in_month(year, months.rem_euclid(12) as u32 + 1, date.day())
+            },
+            Frequency::Yearly => {
+                let month = self.by_month.unwrap_or_else(|| date.month());
+                let year = i32::try_from(step).ok().and_then(|step| date.year().checked_add(step))?;
+                NaiveDate::from_ymd_opt(year, 1, 1)?;
+                self.days_in_month(year, month, date.day())
+            },
+        };
+        Some(days.into_iter()
+            .filter_map(|day| localize(&anchor.timezone(), day.and_time(anchor.naive_local().time())))
+            .filter(|&time| time >= anchor)
+            .collect())
+    }
+
+    /// Returns the sorted days of the given month selected by BYDAY and BYMONTHDAY, falling back
+    /// to `default_day`, clamped to the length of the month.
+    fn days_in_month(&self, year: i32, month: u32, default_day: u32) -> Vec<NaiveDate> {
+        let last = match last_day_of_month(year, month) {
+            Some(last) => last,
+            None => return Vec::new(),
+        };
+        let month_day = self.by_month_day.map(|day| match day {
+            day if day < 0 => (last as i32 + 1 + day as i32).max(1) as u32,
+            day => (day as u32).min(last),
+        });
+        let mut days: Vec<u32> = if self.by_day.is_empty() {
+            vec![month_day.unwrap_or_else(|| default_day.min(last))]
+        } else {
+            let first_weekday = NaiveDate::from_ymd(year, month, 1).weekday();
+            let mut days = Vec::new();
+            for by in &self.by_day {
+                let offset = (7 + by.weekday.num_days_from_monday() - first_weekday.num_days_from_monday()) % 7;
+                let matching: Vec<u32> = (1 + offset..=last).step_by(7).collect();
+                match by.ordinal {
+                    0 => days.extend(matching),
+                    n if n > 0 => days.extend(matching.get(n as usize - 1)),
+                    n => days.extend(matching.len().checked_sub(n.unsigned_abs() as usize).map(|i| matching[i])),
+                }
+            }
+            // BYMONTHDAY narrows BYDAY down, as in "Friday the 13th"
+            if let Some(month_day) = month_day {
+                days.retain(|&day| day == month_day);
+            }
+            days
+        };
+        days.sort_unstable();
+        days.dedup();
+        days.into_iter().map(|day| NaiveDate::from_ymd(year, month, day)).collect()
+    }
+
+    /// Estimates how many periods after the anchor's the given time falls.
+    fn period_of<Tz: TimeZone>(&self, anchor: &DateTime<Tz>, time: &DateTime<Tz>) -> i64 {
+        let (from, to) = (anchor.date().naive_local(), time.date().naive_local());
+        let periods = match self.frequency {
+            Frequency::Daily => (to - from).num_days(),
+            Frequency::Weekly => (to - from).num_days() / 7,
+            Frequency::Monthly => (to.year() - from.year()) as i64 * 12 + to.month() as i64 - from.month() as i64,
+            Frequency::Yearly => (to.year() - from.year()) as i64,
+        };
+        periods / self.interval.max(1) as i64
+    }
+}
+
+// Recurrences are written as the body of an RFC 5545 RRULE, e.g. "FREQ=MONTHLY;BYDAY=-1FR".
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let frequency = match self.frequency {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+            Frequency::Yearly => "YEARLY",
+        };
+        write!(f, "FREQ={}", frequency)?;
+        if self.interval != 1 { write!(f, ";INTERVAL={}", self.interval)?; }
+        if let Some(month) = self.by_month { write!(f, ";BYMONTH={}", month)?; }
+        if let Some(day) = self.by_month_day { write!(f, ";BYMONTHDAY={}", day)?; }
+        if !self.by_day.is_empty() {
+            let days: Vec<String> = self.by_day.iter().map(|by| by.to_string()).collect();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = ParseRuleError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let mut rule = Recurrence {
+            frequency: Frequency::Daily,
+            interval: 1,
+            by_day: Vec::new(),
+            by_month_day: None,
+            by_month: None,
+        };
+        let mut frequency = None;
+        for part in string.split(';').filter(|part| !part.is_empty()) {
+            let invalid = || ParseRuleError(part.to_string());
+            let (key, value) = match part.split_once('=') {
+                Some(pair) => pair,
+                None => return Err(invalid()),
+            };
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => frequency = Some(match value.to_ascii_uppercase().as_str() {
+                    "DAILY" => Frequency::Daily,
+                    "WEEKLY" => Frequency::Weekly,
+                    "MONTHLY" => Frequency::Monthly,
+                    "YEARLY" => Frequency::Yearly,
+                    _ => return Err(invalid()),
+                }),
+                "INTERVAL" => rule.interval = match value.parse() {
+                    Ok(interval) if interval > 0 => interval,
+                    _ => return Err(invalid()),
+                },
+                "BYMONTH" => rule.by_month = match value.parse() {
+                    Ok(month) if (1..=12).contains(&month) => Some(month),
+                    _ => return Err(invalid()),
+                },
+                "BYMONTHDAY" => rule.by_month_day = match value.parse::<i8>() {
+                    Ok(day) if day != 0 && (-31..=31).contains(&day) => Some(day),
+                    _ => return Err(invalid()),
+                },
+                "BYDAY" => for day in value.split(',') {
+                    rule.by_day.push(day.parse().map_err(|_| invalid())?);
+                },
+                _ => return Err(invalid()),
+            }
+        }
+        rule.frequency = match frequency {
+            Some(frequency) => frequency,
+            None => return Err(ParseRuleError(String::from("FREQ"))),
+        };
+        // reject the combinations that this implementation does not give a meaning to
+        let ordinals = rule.by_day.iter().any(|by| by.ordinal != 0);
+        match rule.frequency {
+            Frequency::Daily | Frequency::Weekly if ordinals || rule.by_month_day.is_some() || rule.by_month.is_some()
+                => Err(ParseRuleError(string.to_string())),
+            Frequency::Monthly if rule.by_month.is_some() => Err(ParseRuleError(string.to_string())),
+            _ => Ok(rule),
+        }
+    }
+}
+
+impl TryFrom<String> for Recurrence {
+    type Error = ParseRuleError;
+
+    fn try_from(string: String) -> Result<Self, Self::Error> {
+        string.parse()
+    }
+}
+
+impl From<Recurrence> for String {
+    fn from(rule: Recurrence) -> Self {
+        rule.to_string()
+    }
+}
+
+impl fmt::Display for WeekdayNum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.ordinal != 0 { write!(f, "{}", self.ordinal)?; }
+        let day = match self.weekday {
+            Weekday::Mon => "MO",
+            Weekday::Tue => "TU",
+            Weekday::Wed => "WE",
+            Weekday::Thu => "TH",
+            Weekday::Fri => "FR",
+            Weekday::Sat => "SA",
+            Weekday::Sun => "SU",
+        };
+        write!(f, "{}", day)
+    }
+}
+
+impl FromStr for WeekdayNum {
+    type Err = ParseRuleError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseRuleError(string.to_string());
+        if string.len() < 2 || !string.is_char_boundary(string.len() - 2) { return Err(invalid()); }
+        let (ordinal, day) = string.split_at(string.len() - 2);
+        let weekday = match day.to_ascii_uppercase().as_str() {
+            "MO" => Weekday::Mon,
+            "TU" => Weekday::Tue,
+            "WE" => Weekday::Wed,
+            "TH" => Weekday::Thu,
+            "FR" => Weekday::Fri,
+            "SA" => Weekday::Sat,
+            "SU" => Weekday::Sun,
+            _ => return Err(invalid()),
+        };
+        let ordinal = match ordinal {
+            "" => 0,
+            ordinal => match ordinal.trim_start_matches('+').parse::<i8>() {
+                Ok(n) if n != 0 && (-5..=5).contains(&n) => n,
+                _ => return Err(invalid()),
+            },
+        };
+        Ok(WeekdayNum { ordinal, weekday })
+    }
+}
+
+impl fmt::Display for ParseRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unsupported recurrence rule part: {}", self.0)
+    }
+}
+
+/// Adds a number of days to a date, or returns None if the result cannot be represented.
+fn add_days(date: NaiveDate, days: i64) -> Option<NaiveDate> {
+    date.checked_add_signed(Duration::days(i32::try_from(days).ok()?.into()))
+}
+
+/// Returns true if the date is the last day of its month.
+fn is_last_of_month(date: NaiveDate) -> bool {
+    date.succ_opt().is_none_or(|next| next.month() != date.month())
+}
+
+/// Returns the number of the last day of the given month.
+fn last_day_of_month(year: i32, month: u32) -> Option<u32> {
+    let first_of_next = match month {
+        12 => NaiveDate::from_ymd_opt(year.checked_add(1)?, 1, 1),
+        _ => NaiveDate::from_ymd_opt(year, month + 1, 1),
+    };
+    first_of_next.and_then(|date| date.pred_opt()).map(|date| date.day())
+}
+
+/// Interprets a naive date and time in the given timezone. Times skipped by a DST change are moved
+/// forward by an hour.
+fn localize<Tz: TimeZone>(zone: &Tz, naive: NaiveDateTime) -> Option<DateTime<Tz>> {
+    zone.from_local_datetime(&naive)
+        .earliest()
+        .or_else(|| zone.from_local_datetime(&(naive + Duration::hours(1))).earliest())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use super::*;
+
+    fn day(year: i32, month: u32, day: u32) -> DateTime<Utc> {
+        Utc.ymd(year, month, day).and_hms(9, 0, 0)
+    }
+
+    /// Returns the first `count` occurrences of the rule, starting with the anchor.
+    fn occurrences(rule: &str, anchor: DateTime<Utc>, count: usize) -> Vec<DateTime<Utc>> {
+        let rule: Recurrence = rule.parse().unwrap();
+        let mut occurrences = vec![anchor];
+        while occurrences.len() < count {
+            let last = *occurrences.last().unwrap();
+            occurrences.push(rule.after(anchor, last).unwrap());
+        }
+        occurrences
+    }
+
+    #[test]
+    fn month_end() {
+        let months = [day(2021, 1, 31), day(2021, 2, 28), day(2021, 3, 31), day(2021, 4, 30)];
+        // a day past the end of a month is clamped to it, without moving later occurrences
+        assert_eq!(occurrences("FREQ=MONTHLY;BYMONTHDAY=31", day(2021, 1, 31), 4), months);
+        assert_eq!(occurrences("FREQ=MONTHLY", day(2021, 1, 31), 4), months);
+        assert_eq!(occurrences("FREQ=MONTHLY;BYMONTHDAY=-1", day(2021, 1, 31), 4), months);
+        assert_eq!(occurrences("FREQ=MONTHLY;BYMONTHDAY=30", day(2021, 1, 30), 3), [day(2021, 1, 30), day(2021, 2, 28), day(2021, 3, 30)]);
+        assert_eq!(
+            occurrences("FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=29", day(2020, 2, 29), 5),
+            [day(2020, 2, 29), day(2021, 2, 28), day(2022, 2, 28), day(2023, 2, 28), day(2024, 2, 29)],
+        );
+    }
+
+    #[test]
+    fn weekdays() {
+        assert_eq!(occurrences("FREQ=MONTHLY;BYDAY=-1FR", day(2021, 1, 29), 3), [day(2021, 1, 29), day(2021, 2, 26), day(2021, 3, 26)]);
+        assert_eq!(
+            occurrences("FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,FR", day(2021, 1, 4), 4),
+            [day(2021, 1, 4), day(2021, 1, 8), day(2021, 1, 18), day(2021, 1, 22)],
+        );
+        assert_eq!(occurrences("FREQ=MONTHLY;BYDAY=FR;BYMONTHDAY=13", day(2021, 8, 13), 2), [day(2021, 8, 13), day(2022, 5, 13)]);
+        // a rule that never occurs gives up
+        let never: Recurrence = "FREQ=MONTHLY;BYDAY=5MO;BYMONTHDAY=1".parse().unwrap();
+        assert_eq!(never.at_or_after(day(2021, 1, 1), day(2021, 1, 2)), None);
+    }
+
+    #[test]
+    fn huge_intervals_end() {
+        for rule in ["FREQ=DAILY;INTERVAL=4000000000", "FREQ=WEEKLY;INTERVAL=4000000000", "FREQ=MONTHLY;INTERVAL=4000000000", "FREQ=YEARLY;INTERVAL=4000000000"] {
+            let rule: Recurrence = rule.parse().unwrap();
+            assert_eq!(rule.after(day(2030, 1, 1), day(2030, 1, 1)), None, "{}", rule);
+        }
+        // periods within range still occur
+        let rule: Recurrence = "FREQ=YEARLY;INTERVAL=200000".parse().unwrap();
+        assert_eq!(rule.after(day(2030, 1, 1), day(2030, 1, 1)), Some(day(202030, 1, 1)));
+    }
+
+    #[test]
+    fn infer() {
+        let inferred = |from, to| Recurrence::infer(from, to).map(|rule| rule.to_string());
+        assert_eq!(inferred(day(2021, 1, 31), day(2021, 2, 28)).as_deref(), Some("FREQ=MONTHLY;BYMONTHDAY=-1"));
+        assert_eq!(inferred(day(2021, 1, 30), day(2021, 2, 28)).as_deref(), Some("FREQ=MONTHLY;BYMONTHDAY=30"));
+        assert_eq!(inferred(day(2021, 1, 15), day(2021, 4, 15)).as_deref(), Some("FREQ=MONTHLY;INTERVAL=3;BYMONTHDAY=15"));
+        assert_eq!(inferred(day(1970, 1, 1), day(1971, 1, 1)).as_deref(), Some("FREQ=YEARLY;BYMONTH=1;BYMONTHDAY=1"));
+        assert_eq!(inferred(day(2021, 1, 4), day(2021, 1, 18)).as_deref(), Some("FREQ=WEEKLY;INTERVAL=2"));
+        assert_eq!(inferred(day(2021, 1, 4), day(2021, 1, 7)).as_deref(), Some("FREQ=DAILY;INTERVAL=3"));
+        assert_eq!(inferred(day(2021, 1, 4), day(2021, 1, 4) + Duration::hours(1)), None);
+        assert_eq!(inferred(day(2021, 1, 4), day(2021, 1, 4)), None);
+    }
+
+    #[test]
+    fn parse_and_display() {
+        for rule in ["FREQ=DAILY", "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,FR", "FREQ=MONTHLY;BYDAY=-1FR", "FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=29"] {
+            assert_eq!(rule.parse::<Recurrence>().unwrap().to_string(), rule);
+        }
+        assert_eq!("freq=monthly;byday=+2tu".parse::<Recurrence>().unwrap().to_string(), "FREQ=MONTHLY;BYDAY=2TU");
+        assert_eq!("BYDAY=MO".parse::<Recurrence>(), Err(ParseRuleError(String::from("FREQ"))));
+        assert_eq!("FREQ=MONTHLY;BYSETPOS=1".parse::<Recurrence>(), Err(ParseRuleError(String::from("BYSETPOS=1"))));
+        for rule in ["FREQ=DAILY;BYMONTHDAY=1", "FREQ=WEEKLY;BYDAY=2TU", "FREQ=MONTHLY;BYMONTH=1", "FREQ=DAILY;INTERVAL=0", "FREQ=MONTHLY;BYDAY=6MO"] {
+            assert!(rule.parse::<Recurrence>().is_err(), "{}", rule);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Contains a parser for relative and natural-language dates, as an alternative to ISO 8601 in the
+//! insert format.
+//!
+//! An expression is a day, optionally followed by a time of day. Days may be `today`, `tomorrow`,
+//! `yesterday`, a weekday such as `fri` or `next friday` (the soonest one after today), an offset
+//! such as `+3d`, `-1w`, `+2m` or `+1y`, or a phrase such as `in 2 weeks`. Times may be written as
+//! `14:00`, `14`, `2pm` or `9:30am`. Without a time, the day starts at midnight. Additionally,
+//! `now`, `+4h`, `in 30 minutes` and the like are counted from the current time, and `eod`, `eow`
+//! and `eom` are the last minute of the current day, week and month.
+
+use chrono::{
+    DateTime,
+    Datelike,
+    Duration,
+    NaiveDate,
+    NaiveTime,
+    TimeZone,
+    Weekday,
+};
+use std::convert::TryFrom;
+
+/// Attempts to resolve a relative expression against `now`, in the timezone `now` is in. Returns
+/// None if the expression is not understood, or names a local time that does not exist.
+pub fn parse<Tz: TimeZone>(input: &str, now: DateTime<Tz>) -> Option<DateTime<Tz>> {
+    let input = input.trim().to_lowercase();
+    let words: Vec<&str> = input.split_whitespace().collect();
+    let zone = now.timezone();
+    let today = now.date().naive_local();
+    let end_of_day = NaiveTime::from_hms(23, 59, 0);
+    // expressions that stand alone
+    match words.as_slice() {
+        ["now"] => return Some(now),
+        ["eod"] => return localize(&zone, today, end_of_day),
+        ["eow"] => return localize(&zone, today + Duration::days(6 - today.weekday().num_days_from_monday() as i64), end_of_day),
+        ["eom"] => return localize(&zone, add_months(today, 1)?.with_day(1)?.pred(), end_of_day),
+        [offset] if offset.len() > 1 && (offset.starts_with('+') || offset.starts_with('-')) => {
+            let (amount, unit) = offset.split_at(offset.trim_end_matches(char::is_alphabetic).len());
+            if let Some(unit) = exact_unit(unit) { return now.checked_add_signed(milliseconds(amount.parse().ok()?, unit)?); }
+        },
+        ["in", amount, unit] => {
+            if let Some(unit) = exact_unit(unit) { return now.checked_add_signed(milliseconds(amount.parse().ok()?, unit)?); }
+        },
+        _ => {},
+    }
+    // otherwise, a day followed by an optional time
+    let (day, rest) = match words.as_slice() {
+        ["today", rest @ ..] => (today, rest),
+        ["tomorrow", rest @ ..] | ["tmr", rest @ ..] => (today.succ(), rest),
+        ["yesterday", rest @ ..] => (today.pred(), rest),
+        ["next", weekday, rest @ ..] => (next_weekday(today, weekday_from_name(weekday)?), rest),
+        ["in", amount, unit, rest @ ..] => (add_nominal(today, amount.parse().ok()?, unit)?, rest),
+        [first, rest @ ..] => match weekday_from_name(first) {
+            Some(weekday) => (next_weekday(today, weekday), rest),
+            None if first.len() > 1 && (first.starts_with('+') || first.starts_with('-')) => {
+                let (amount, unit) = first.split_at(first.trim_end_matches(char::is_alphabetic).len());
+                (add_nominal(today, amount.parse().ok()?, unit)?, rest)
+            },
+            None => return None,
+        },
+        [] => return None,
+    };
+    let time = match rest {
+        [] => NaiveTime::from_hms(0, 0, 0),
+        [time] => time_from_str(time)?,
+        _ => return None,
+    };
+    localize(&zone, day, time)
+}
+
+/// Returns the length in milliseconds of an hour or a minute, which are exact units. Returns None
+/// for other units.
+fn exact_unit(unit: &str) -> Option<i64> {
+    match unit {
+        "h" | "hr" | "hrs" | "hour" | "hours" => Some(3_600_000),
+        "min" | "mins" | "minute" | "minutes" => Some(60_000),
+        _ => None,
+    }
+}
+
+/// Moves a date by an amount of days, weeks, months or years, clamping to the end of shorter
+/// months. Returns None for other units, or if the date would be out of range.
+fn add_nominal(date: NaiveDate, amount: i64, unit: &str) -> Option<NaiveDate> {
+    match unit {
+        "d" | "day" | "days" => date.checked_add_signed(milliseconds(amount, 86_400_000)?),
+        "w" | "wk" | "wks" | "week" | "weeks" => date.checked_add_signed(milliseconds(amount, 604_800_000)?),
+        "m" | "mo" | "month" | "months" => add_months(date, amount),
+        "y" | "yr" | "yrs" | "year" | "years" => add_months(date, amount.checked_mul(12)?),
+        _ => None,
+    }
+}
+
+fn add_months(date: NaiveDate, amount: i64) -> Option<NaiveDate> {
+    let months = (date.year() as i64 * 12 + date.month0() as i64).checked_add(amount)?;
+    let (year, month) = (i32::try_from(months.div_euclid(12)).ok()?, months.rem_euclid(12) as u32 + 1);
+    (1..=date.day()).rev().find_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+}
+
+/// Returns a duration of `amount` units of `unit` milliseconds, or None if it is too long.
+fn milliseconds(amount: i64, unit: i64) -> Option<Duration> {
+    amount.checked_mul(unit).map(Duration::milliseconds)
+}
+
+/// Returns the soonest date after `date` that falls on the given weekday.
+fn next_weekday(date: NaiveDate, weekday: Weekday) -> NaiveDate {
+    let ahead = (7 + weekday.num_days_from_monday() - date.weekday().num_days_from_monday()) % 7;
+    date + Duration::days(if ahead == 0 { 7 } else { ahead as i64 })
+}
+
+/// Parses "14:00", "14", "2pm" or "9:30am".
+fn time_from_str(string: &str) -> Option<NaiveTime> {
+    let (clock, meridiem) = if let Some(clock) = string.strip_suffix("am") {
+        (clock, Some(0))
+    } else if let Some(clock) = string.strip_suffix("pm") {
+        (clock, Some(12))
+    } else {
+        (string, None)
+    };
+    let (hour, minute) = match clock.split_once(':') {
+        Some((hour, minute)) if minute.len() == 2 => (hour.parse::<u32>().ok()?, minute.parse().ok()?),
+        Some(_) => return None,
+        None => (clock.parse::<u32>().ok()?, 0),
+    };
+    let hour = match meridiem {
+        Some(offset) if (1..=12).contains(&hour) => hour % 12 + offset,
+        Some(_) => return None,
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+fn weekday_from_name(name: &str) -> Option<Weekday> {
+    match name {
+        "mon" | "monday" => Some(Weekday::Mon),
+        "tue" | "tues" | "tuesday" => Some(Weekday::Tue),
+        "wed" | "wednesday" => Some(Weekday::Wed),
+        "thu" | "thur" | "thurs" | "thursday" => Some(Weekday::Thu),
+        "fri" | "friday" => Some(Weekday::Fri),
+        "sat" | "saturday" => Some(Weekday::Sat),
+        "sun" | "sunday" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+fn localize<Tz: TimeZone>(zone: &Tz, date: NaiveDate, time: NaiveTime) -> Option<DateTime<Tz>> {
+    zone.from_local_datetime(&date.and_time(time)).earliest()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use super::*;
+
+    #[test]
+    fn out_of_range() {
+        let now = Utc.ymd(2021, 1, 1).and_hms(12, 0, 0);
+        for input in ["+99999999999d", "+9999999999999999h", "+999999999999999999y", "in 99999999999 days", "-999999999999w"] {
+            assert_eq!(parse(input, now), None, "{}", input);
+        }
+        assert_eq!(parse("+1y", now), Some(Utc.ymd(2022, 1, 1).and_hms(0, 0, 0)));
+        assert_eq!(parse("in 90 minutes", now), Some(Utc.ymd(2021, 1, 1).and_hms(13, 30, 0)));
+    }
+}
@@ -1,30 +1,68 @@
-/// Contains the definition and logic of the State struct.
+//! Contains the definition and logic of the State struct.
 
 use crate::{
-    datetime_from_iso,
+    backup::Backup,
     error::Error,
     event_to_record,
+    occurrence_to_record,
     Event,
     Interval,
+    Override,
+    Recurrence,
+    Span,
+    Zone,
 };
-use std::default::Default;
+use chrono::{DateTime, Local};
+use clamendar::{filter::Filter, iso::{self, IsoDuration}, relative};
+use std::{default::Default, mem};
 use tui::widgets::{ ListState, TableState };
+use uuid::Uuid;
 
-/// Describes the "Focus" of the interface: whether it is in insert mode or focused on one or zero
-/// panes.
+/// Describes the "Focus" of the interface: whether it is in insert mode, typing a search, or
+/// focused on one or zero panes.
 pub enum Focus {
+    Backups,
     InputAdd,
     Intervals,
+    Search,
     Timed,
     Untimed,
     None,
 }
 
+/// Describes which occurrences of a repeating event a deletion or yank applies to.
+pub enum Scope {
+    Occurrence,
+    All,
+}
+
+/// Remembers where a yanked event came from, so that adding the buffer back amends the event
+/// instead of starting from scratch.
+pub enum Edit {
+    /// The event with this id is in the buffer, and keeps the id when added back, along with these
+    /// skipped and replaced occurrences if it still repeats.
+    All {
+        id: Uuid,
+        exceptions: Vec<DateTime<Local>>,
+        overrides: Vec<Override>,
+    },
+    /// A single occurrence of the contained event is in the buffer. The event is held here until
+    /// the occurrence is added back.
+    Occurrence(Event),
+}
+
 /// Struct to consolidate all of the persistent state of the program.
 pub struct State {
+    // backups listed for restoring, most recent first
+    pub backups: Vec<Backup>,
+    pub backups_state: ListState,
     // the insertion buffer
     pub buffer: String,
     pub cursor_offset: usize,
+    // whether events changed since they were last saved
+    pub dirty: bool,
+    // what the insertion buffer was yanked from, if anything
+    pub editing: Option<Edit>,
     pub focus: Focus,
     pub intervals: Vec<Event>,
     // the offset describes which element should be focused while the pane is selected
@@ -32,6 +70,11 @@ pub struct State {
     // see the `tui-rs` documentation for information on stateful widgets
     pub intervals_state: TableState,
     pub last_error: Option<Error>,
+    // the search typed after '/'; while it is not empty, the panes only show the events matching
+    // it, and offsets and selections count among those
+    pub query: String,
+    // the query as a filter expression, if it is one
+    query_filter: Option<Filter>,
     pub timed: Vec<Event>,
     pub timed_offset: usize,
     pub timed_state: TableState,
@@ -41,56 +84,53 @@ pub struct State {
 }
 
 impl State {
-    /// Attempts to parse the string in the insertion buffer and add the described event.
-    pub fn add_event_from_buffer(&mut self) -> Result<(), Error> {
-        if self.buffer.is_empty() { return Err(Error::NoInfo); }
-        if !self.buffer.contains('\t') { return Err(Error::InvalidRecord); }
-        let mut event = Event {
-            start: None,
-            interval: Interval::None,
-            description: String::new(),
-        };
-        let mut halves = self.buffer.split('\t');
-        match halves.next() {
-            Some("") => {}, // leave event.start empty.
-            Some(iso) => {
-                let mut tokens = iso.split('/');
-                match (tokens.next(), tokens.next(), tokens.next()) {
-                    (Some(repetition), Some(start), Some(end)) => {
-                        match repetition.strip_prefix('R') {
-                            Some("") => event.interval = Interval::RepIndefinite(datetime_from_iso(end)?),
-                            Some(string) => {
-                                let occurrences = match string.parse::<usize>() {
-                                    Ok(num) => num,
-                                    Err(_) => return Err(Error::InvalidIso),
-                                };
-                                event.interval = Interval::RepDefinite {
-                                    occurrences,
-                                    end: datetime_from_iso(end)?,
-                                };
-                            },
-                            None => return Err(Error::InvalidIso),
-                        }
-                        event.start = Some(datetime_from_iso(start)?);
-                    },
-                    (Some(start), Some(end), None) => {
-                        event.start = Some(datetime_from_iso(start)?);
-                        event.interval = Interval::Standard(datetime_from_iso(end)?);
-                    },
-                    (Some(start), None, None) => {
-                        event.start = Some(datetime_from_iso(start)?);
-                        event.interval = Interval::None;
-                    },
-                    _ => {},
+    /// Rolls every repeating event in the timed pane forward until its next occurrence is
+    /// upcoming, dropping definite repeating events that run out of occurrences. Returns true if
+    /// anything changed.
+    pub fn advance_repeating(&mut self) -> bool {
+        let stale = |event: &Event| !event.is_upcoming()
+            && matches!(event.interval, Interval::RepDefinite { .. } | Interval::RepIndefinite(_));
+        if !self.timed.iter().any(stale) { return false; }
+        self.timed.retain_mut(|event| event.advance_to_upcoming());
+        self.timed.sort_unstable();
+        self.clamp_timed_selection();
+        self.dirty = true;
+        true
+    }
+
+    /// Attempts to parse the string in the insertion buffer and add the described event. Returns
+    /// the id of the event added, or amended in the case of a single occurrence.
+    pub fn add_event_from_buffer(&mut self) -> Result<Uuid, Error> {
+        let mut event = self.event_from_buffer()?;
+        self.dirty = true;
+        match self.editing.take() {
+            // a single yanked occurrence goes back into its event, unless it no longer fits there
+            Some(Edit::Occurrence(mut series)) => match (event.start, &event.interval) {
+                (Some(start), Interval::None) => {
+                    series.override_current(start, event.description);
+                    let id = series.id;
+                    self.timed.push(series);
+                    self.timed.sort_unstable();
+                    self.buffer.clear();
+                    return Ok(id);
+                },
+                _ => if series.except_current() {
+                    self.timed.push(series);
+                    self.timed.sort_unstable();
+                },
+            },
+            Some(Edit::All { id, exceptions, overrides }) => {
+                if let Interval::RepDefinite { .. } | Interval::RepIndefinite(_) = event.interval {
+                    event.exceptions = exceptions;
+                    event.overrides = overrides;
                 }
+                event.id = id;
             },
-            None => {}, // leave event.start empty
+            None => {},
         }
-        match halves.next() {
-            Some(string) => event.description.push_str(string.trim()),
-            None => {}, // leave event.description empty
-        }
-        if event.start == None && event.description == "" { return Err(Error::NoInfo); }
+        // the yanked event may have come back in the meantime, as when the events are reloaded
+        if self.find(event.id).is_some() { event.id = Uuid::new_v4(); }
+        let id = event.id;
         match (event.start, &event.interval) {
             (None, _) => {
                 self.untimed.push(event);
@@ -106,7 +146,78 @@ impl State {
             },
         }
         self.buffer.clear();
-        Ok(())
+        Ok(id)
+    }
+
+    /// Puts an event held for editing a single occurrence back where it belongs, untouched.
+    pub fn cancel_edit(&mut self) {
+        if let Some(Edit::Occurrence(event)) = self.editing.take() {
+            self.timed.push(event);
+            self.timed.sort_unstable();
+        }
+    }
+
+    /// Carries out a deletion or yank of the selected repeating event that is waiting on a choice
+    /// of scope. There is no choice of "this and following": past occurrences are never kept, so
+    /// the current occurrence and those following it are all of them.
+    pub fn choose_scope(&mut self, scope: Scope) {
+        let mut event = match (&self.focus, &self.last_error, self.selected()) {
+            (Focus::Timed, Some(Error::DeletionScope), Some(id))
+                | (Focus::Timed, Some(Error::YankScope), Some(id))
+            => match self.remove(id) {
+                Some(event) => event,
+                None => return,
+            },
+            _ => return,
+        };
+        match (&self.last_error, scope) {
+            (Some(Error::DeletionScope), Scope::Occurrence) => {
+                // skipping the last occurrence of a definite repeating event leaves nothing of it
+                let remaining = event.except_current();
+                if remaining {
+                    self.timed.push(event);
+                    self.timed.sort_unstable();
+                }
+            },
+            (Some(Error::DeletionScope), _) => {},
+            (Some(Error::YankScope), scope) => {
+                self.cancel_edit();
+                match scope {
+                    Scope::Occurrence => {
+                        self.buffer = occurrence_to_record(&event);
+                        self.editing = Some(Edit::Occurrence(event));
+                    },
+                    Scope::All => {
+                        self.editing = Some(Edit::All {
+                            id: event.id,
+                            exceptions: mem::take(&mut event.exceptions),
+                            overrides: mem::take(&mut event.overrides),
+                        });
+                        self.buffer = event_to_record(&event);
+                    },
+                }
+                self.dirty = true;
+                self.last_error = None;
+                self.clamp_timed_selection();
+                self.focus(Focus::InputAdd);
+                return;
+            },
+            _ => {},
+        }
+        self.dirty = true;
+        self.last_error = None;
+        self.clamp_timed_selection();
+    }
+
+    /// Keeps the selection in the timed pane within bounds after events were removed from it.
+    fn clamp_timed_selection(&mut self) {
+        let len = self.visible(&self.timed).len();
+        if self.timed_offset >= len {
+            self.timed_offset = len.saturating_sub(1);
+        }
+        if let Focus::Timed = self.focus {
+            self.timed_state.select(if len == 0 { None } else { Some(self.timed_offset) });
+        }
     }
 
     pub fn cursor_beginning(&mut self) { self.cursor_offset = 0; }
@@ -126,15 +237,13 @@ impl State {
     pub fn delete_selected(&mut self) {
         match self.last_error {
             Some(Error::DeletionWarning) => {
-                match self.focus {
-                    Focus::Intervals => { self.intervals.remove(self.intervals_state.selected().unwrap()); },
-                    Focus::Timed => { self.timed.remove(self.timed_state.selected().unwrap()); },
-                    Focus::Untimed => { self.untimed.remove(self.untimed_state.selected().unwrap()); },
-                    _ => {}, // deletion can't happen anywhere else.
-                }
+                // deletion can't happen anywhere but in a pane
+                if let Some(id) = self.selected() { self.remove(id); }
+                self.dirty = true;
                 self.last_error = None;
             },
             _ => match self.focus {
+                Focus::Timed if self.selected_repeats() => self.last_error = Some(Error::DeletionScope),
                 Focus::Intervals
                     | Focus::Timed
                     | Focus::Untimed
@@ -144,43 +253,257 @@ impl State {
         }
     }
 
+    /// Gathers every event for serialization, including one held while a single occurrence of it
+    /// is edited.
+    pub fn events(&self) -> Vec<&Event> {
+        let held = match &self.editing {
+            Some(Edit::Occurrence(event)) => Some(event),
+            _ => None,
+        };
+        self.intervals.iter().chain(&self.timed).chain(&self.untimed).chain(held).collect()
+    }
+
+    /// Attempts to parse the string in the insertion buffer into an event, without adding it.
+    pub fn event_from_buffer(&self) -> Result<Event, Error> {
+        if self.buffer.is_empty() { return Err(Error::NoInfo); }
+        if !self.buffer.contains('\t') { return Err(Error::InvalidRecord); }
+        let mut event = Event::new(String::new());
+        let mut halves = self.buffer.split('\t');
+        match halves.next() {
+            Some("") => {}, // leave event.start empty.
+            Some(record) => {
+                // an optional RRULE-style suffix describes how a repeating event recurs
+                let (iso, rule) = match record.split_once(';') {
+                    Some((iso, rule)) => match rule.parse::<Recurrence>() {
+                        Ok(rule) => (iso, Some(rule)),
+                        Err(_) => return Err(Error::InvalidRule),
+                    },
+                    None => (record, None),
+                };
+                // a bracketed timezone after any of the dates applies to the whole event
+                let mut zone = None;
+                for token in split_interval(iso) {
+                    if let Some((_, name)) = token.split_once('[') {
+                        let named = name.trim_end_matches(']').parse::<Zone>()?;
+                        if zone.is_some_and(|zone| zone != named) { return Err(Error::InvalidIso); }
+                        zone = Some(named);
+                    }
+                }
+                let mut zone = zone.unwrap_or(Zone::Floating);
+                let mut tokens = split_interval(iso).into_iter();
+                match (tokens.next(), tokens.next(), tokens.next(), tokens.next()) {
+                    (Some(repetition), Some(start), Some(end), None) => {
+                        let (start, end, span) = interval_from_iso(&self.buffer, start, end, &mut zone)?;
+                        // a repeat that does not move forward would stay in the past forever
+                        if end <= start { return Err(Error::InvalidIso); }
+                        match repetition.strip_prefix('R') {
+                            Some("") => event.interval = Interval::RepIndefinite(end),
+                            Some(string) => {
+                                let occurrences = match string.parse::<usize>() {
+                                    Ok(num) => num,
+                                    Err(_) => return Err(Error::InvalidIso),
+                                };
+                                event.interval = Interval::RepDefinite { occurrences, end };
+                            },
+                            None => return Err(Error::InvalidIso),
+                        }
+                        event.start = Some(start);
+                        event.span = span;
+                    },
+                    (Some(start), Some(end), None, None) => {
+                        let (start, end, span) = interval_from_iso(&self.buffer, start, end, &mut zone)?;
+                        event.start = Some(start);
+                        event.interval = Interval::Standard(end);
+                        event.span = span;
+                    },
+                    (Some(start), None, None, None) => {
+                        event.start = Some(datetime_from_iso(&self.buffer, start, &mut zone)?);
+                        event.interval = Interval::None;
+                    },
+                    // more slashes than any interval has
+                    _ => return Err(Error::InvalidIso),
+                }
+                event.zone = zone;
+                match rule {
+                    Some(rule) => if !event.set_rule(rule) { return Err(Error::InvalidRule); },
+                    None => event.infer_rule(),
+                }
+            },
+            None => {}, // leave event.start empty
+        }
+        if let Some(string) = halves.next() {
+            event.description.push_str(string.trim());
+        }
+        if event.start.is_none() && event.description.is_empty() { return Err(Error::NoInfo); }
+        Ok(event)
+    }
+
+    /// Returns the event with the given id, wherever it is.
+    pub fn find(&self, id: Uuid) -> Option<&Event> {
+        self.events().into_iter().find(|event| event.id == id)
+    }
+
     /// Switches the focus of the interface.
     pub fn focus(&mut self, target: Focus) {
         match self.focus {
+            Focus::Backups => self.backups_state.select(None),
             Focus::Intervals => self.intervals_state.select(None),
             Focus::Timed => self.timed_state.select(None),
             Focus::Untimed => self.untimed_state.select(None),
             _ => {},
         }
         match target {
+            Focus::Backups if !self.backups.is_empty() => self.backups_state.select(Some(0)),
             Focus::InputAdd => self.cursor_offset = self.buffer.len(),
-            Focus::Intervals => if !self.intervals.is_empty() { self.intervals_state.select(Some(self.intervals_offset)) },
-            Focus::Timed => if !self.timed.is_empty() { self.timed_state.select(Some(self.timed_offset)) },
-            Focus::Untimed => if !self.untimed.is_empty() { self.untimed_state.select(Some(self.untimed_offset)) },
+            Focus::Intervals if !self.visible(&self.intervals).is_empty() => self.intervals_state.select(Some(self.intervals_offset)),
+            Focus::Timed if !self.visible(&self.timed).is_empty() => self.timed_state.select(Some(self.timed_offset)),
+            Focus::Untimed if !self.visible(&self.untimed).is_empty() => self.untimed_state.select(Some(self.untimed_offset)),
             _ => {},
         }
         self.focus = target;
     }
 
+    /// Selects the next event shown, or the previous one, going from pane to pane and wrapping
+    /// around from the untimed pane back to the intervals. While searching, this jumps between
+    /// matches.
+    pub fn jump(&mut self, forward: bool) {
+        let counts = [self.visible(&self.intervals).len(), self.visible(&self.timed).len(), self.visible(&self.untimed).len()];
+        let total: usize = counts.iter().sum();
+        if total == 0 { return; }
+        // the selection is numbered among the events of every pane, in order
+        let current = match self.focus {
+            Focus::Intervals => self.intervals_state.selected(),
+            Focus::Timed => self.timed_state.selected().map(|index| counts[0] + index),
+            Focus::Untimed => self.untimed_state.selected().map(|index| counts[0] + counts[1] + index),
+            _ => None,
+        };
+        let target = match (current, forward) {
+            (Some(index), true) => (index + 1) % total,
+            (Some(index), false) => (index + total - 1) % total,
+            (None, true) => 0,
+            (None, false) => total - 1,
+        };
+        if target < counts[0] {
+            self.intervals_offset = target;
+            self.focus(Focus::Intervals);
+        } else if target < counts[0] + counts[1] {
+            self.timed_offset = target - counts[0];
+            self.focus(Focus::Timed);
+        } else {
+            self.untimed_offset = target - counts[0] - counts[1];
+            self.focus(Focus::Untimed);
+        }
+    }
+
+    /// Returns the ids of the events each pane has its selection on, to find them again with
+    /// `remark` once the panes change.
+    fn marks(&self) -> [Option<Uuid>; 3] {
+        [
+            self.visible(&self.intervals).get(self.intervals_offset).map(|event| event.id),
+            self.visible(&self.timed).get(self.timed_offset).map(|event| event.id),
+            self.visible(&self.untimed).get(self.untimed_offset).map(|event| event.id),
+        ]
+    }
+
+    /// Returns true if the event matches the search query: if the query is a filter expression
+    /// that the event matches, or if the characters of the query appear in order, ignoring case,
+    /// in the description shown for the event. Every event matches an empty query.
+    fn matches_query(&self, event: &Event) -> bool {
+        let shown = event.current_override().map_or(&event.description, |replacement| &replacement.description);
+        self.query.is_empty()
+            || self.query_filter.as_ref().is_some_and(|filter| filter.matches(event))
+            || fuzzy(&self.query, shown)
+    }
+
+    /// Puts the selection of each pane back on the event it was on. A selected event that is gone
+    /// leaves the selection where it was, as far as possible.
+    fn remark(&mut self, marks: [Option<Uuid>; 3]) {
+        let [intervals, timed, untimed] = marks;
+        let find = |events: Vec<&Event>, mark: Option<Uuid>, offset: usize| mark
+            .and_then(|mark| events.iter().position(|event| event.id == mark))
+            .unwrap_or_else(|| offset.min(events.len().saturating_sub(1)));
+        self.intervals_offset = find(self.visible(&self.intervals), intervals, self.intervals_offset);
+        self.timed_offset = find(self.visible(&self.timed), timed, self.timed_offset);
+        self.untimed_offset = find(self.visible(&self.untimed), untimed, self.untimed_offset);
+        match self.focus {
+            Focus::Intervals => self.intervals_state.select(if self.visible(&self.intervals).is_empty() { None } else { Some(self.intervals_offset) }),
+            Focus::Timed => self.timed_state.select(if self.visible(&self.timed).is_empty() { None } else { Some(self.timed_offset) }),
+            Focus::Untimed => self.untimed_state.select(if self.visible(&self.untimed).is_empty() { None } else { Some(self.untimed_offset) }),
+            _ => {},
+        }
+    }
+
+    /// Replaces every event, splitting them into their panes. Each pane keeps its selection on the
+    /// same event if that is still there, and an occurrence held for editing is kept if its event
+    /// is unchanged. The insertion buffer is left alone.
+    pub fn replace_events(&mut self, mut events: Vec<Event>) {
+        let marks = self.marks();
+        self.editing = match self.editing.take() {
+            Some(Edit::Occurrence(held)) => {
+                // the event must be unchanged, not merely the same one
+                let key = |event: &Event| serde_json::to_string(event).ok();
+                let held = key(&held);
+                events.iter()
+                    .position(|event| key(event) == held)
+                    .map(|index| Edit::Occurrence(events.remove(index)))
+            },
+            editing => editing,
+        };
+        self.intervals.clear();
+        self.timed.clear();
+        self.untimed.clear();
+        for mut event in events {
+            // events saved without a rule repeat in calendar terms if possible
+            event.infer_rule();
+            match (&event.start, &event.interval) {
+                (Some(_), Interval::None)
+                    | (Some(_), Interval::RepDefinite { .. })
+                    | (Some(_), Interval::RepIndefinite(_))
+                => self.timed.push(event),
+                (Some(_), _) => self.intervals.push(event),
+                (None, _) => self.untimed.push(event),
+            }
+        }
+        // events are sorted first chronologically, then alphabetically by description
+        self.intervals.sort_unstable();
+        self.timed.sort_unstable();
+        self.untimed.sort_unstable();
+        self.remark(marks);
+        // repeating events may have passed since they were saved
+        self.advance_repeating();
+    }
+
+    /// Takes the event with the given id out of its pane.
+    pub fn remove(&mut self, id: Uuid) -> Option<Event> {
+        for pane in [&mut self.intervals, &mut self.timed, &mut self.untimed] {
+            if let Some(index) = pane.iter().position(|event| event.id == id) { return Some(pane.remove(index)); }
+        }
+        None
+    }
+
     /// Selects the next item in the selected pane. Does not wrap from bottom to top.
     pub fn scroll_down(&mut self) {
         match self.focus {
+            Focus::Backups => match self.backups_state.selected() {
+                Some(selected) if selected < self.backups.len() - 1 => self.backups_state.select(Some(selected + 1)),
+                _ => {},
+            },
             Focus::Intervals => match self.intervals_state.selected() {
-                Some(selected) if selected < self.intervals.len() - 1 => {
+                Some(selected) if selected < self.visible(&self.intervals).len() - 1 => {
                     self.intervals_offset = selected + 1;
                     self.intervals_state.select(Some(self.intervals_offset));
                 },
                 _ => {},
             },
             Focus::Timed => match self.timed_state.selected() {
-                Some(selected) if selected < self.timed.len() - 1 => {
+                Some(selected) if selected < self.visible(&self.timed).len() - 1 => {
                     self.timed_offset = selected + 1;
                     self.timed_state.select(Some(self.timed_offset));
                 },
                 _ => {},
             },
             Focus::Untimed => match self.untimed_state.selected() {
-                Some(selected) if selected < self.untimed.len() - 1 => {
+                Some(selected) if selected < self.visible(&self.untimed).len() - 1 => {
                     self.untimed_offset = selected + 1;
                     self.untimed_state.select(Some(self.untimed_offset));
                 },
@@ -193,6 +516,10 @@ impl State {
     /// Selects the previous item in the selected pane. Does not wrap from top to bottom.
     pub fn scroll_up(&mut self) {
         match self.focus {
+            Focus::Backups => match self.backups_state.selected() {
+                Some(selected) if selected > 0 => self.backups_state.select(Some(selected - 1)),
+                _ => {},
+            },
             Focus::Intervals => match self.intervals_state.selected() {
                 Some(selected) if selected > 0 => {
                     self.intervals_offset = selected - 1;
@@ -218,13 +545,37 @@ impl State {
         }
     }
 
-    /// Unifies the three event vectors into one. Used in preparation for serialization.
-    pub fn take_all(&mut self) -> Vec<Event> {
-        let mut vec: Vec<Event> = Vec::new();
-        vec.append(&mut self.intervals);
-        vec.append(&mut self.timed);
-        vec.append(&mut self.untimed);
-        vec
+    /// Returns the id of the selected event in the focused pane, if any.
+    pub fn selected(&self) -> Option<Uuid> {
+        let event = match self.focus {
+            Focus::Intervals => self.intervals_state.selected().and_then(|index| self.visible(&self.intervals).get(index).copied()),
+            Focus::Timed => self.timed_state.selected().and_then(|index| self.visible(&self.timed).get(index).copied()),
+            Focus::Untimed => self.untimed_state.selected().and_then(|index| self.visible(&self.untimed).get(index).copied()),
+            _ => None,
+        };
+        event.map(|event| event.id)
+    }
+
+    /// Returns true if the selected event in the timed pane repeats.
+    fn selected_repeats(&self) -> bool {
+        match self.timed_state.selected().and_then(|index| self.visible(&self.timed).get(index).copied()) {
+            Some(event) => matches!(event.interval, Interval::RepDefinite { .. } | Interval::RepIndefinite(_)),
+            None => false,
+        }
+    }
+
+    /// Narrows the panes down to the events matching the query, or shows every event again if it
+    /// is empty. Each pane keeps its selection on the same event if that is still shown.
+    pub fn set_query(&mut self, query: String) {
+        let marks = self.marks();
+        self.query_filter = query.parse().ok();
+        self.query = query;
+        self.remark(marks);
+    }
+
+    /// Returns the events of a pane that are shown, which are those matching the search query.
+    pub fn visible<'a>(&self, pane: &'a [Event]) -> Vec<&'a Event> {
+        pane.iter().filter(|event| self.matches_query(event)).collect()
     }
 
     /// Shows a warning about yanking the currently selected item. If the warning is already
@@ -233,16 +584,25 @@ impl State {
     pub fn yank_selected(&mut self) {
         match self.last_error {
             Some(Error::YankWarning) => {
-                match self.focus {
-                    Focus::Intervals => { self.buffer = event_to_record(self.intervals.remove(self.intervals_state.selected().unwrap())); }
-                    Focus::Timed => { self.buffer = event_to_record(self.timed.remove(self.timed_state.selected().unwrap())); }
-                    Focus::Untimed => { self.buffer = event_to_record(self.untimed.remove(self.untimed_state.selected().unwrap())); }
-                    _ => {}, // yanking can't happen anywhere else.
-                }
+                // yanking can't happen anywhere but in a pane
+                let mut event = match self.selected().and_then(|id| self.remove(id)) {
+                    Some(event) => event,
+                    None => return,
+                };
+                // the buffer no longer holds a yanked occurrence, so its event goes back untouched
+                self.cancel_edit();
+                self.editing = Some(Edit::All {
+                    id: event.id,
+                    exceptions: mem::take(&mut event.exceptions),
+                    overrides: mem::take(&mut event.overrides),
+                });
+                self.buffer = event_to_record(&event);
+                self.dirty = true;
                 self.last_error = None;
                 self.focus(Focus::InputAdd);
             },
             _ => match self.focus {
+                Focus::Timed if self.selected_repeats() => self.last_error = Some(Error::YankScope),
                 Focus::Intervals
                     | Focus::Timed
                     | Focus::Untimed
@@ -253,16 +613,101 @@ impl State {
     }
 }
 
+/// Attempts to parse a chrono DateTime from `token`, a slice of the insertion `buffer`, in the given
+/// timezone, or failing that as a relative expression. A bracketed timezone after the date is
+/// ignored, and an offset from UTC settles the timezone of a floating event. Columns in parse
+/// errors count from the start of the buffer.
+fn datetime_from_iso(buffer: &str, token: &str, zone: &mut Zone) -> Result<DateTime<Local>, Error> {
+    let token = token.split('[').next().unwrap_or(token);
+    let offset = token.as_ptr() as usize - buffer.as_ptr() as usize;
+    match iso::parse(token) {
+        Ok(parsed) => {
+            if let (Zone::Floating, Some(offset)) = (*zone, parsed.offset) { *zone = Zone::Fixed(offset); }
+            parsed.in_zone(zone).map(|time| time.with_timezone(&Local)).ok_or(Error::InvalidTime)
+        },
+        // fall back to expressions like "tomorrow 14:00", but report the ISO error if that fails
+        Err(error) => relative::parse(token, Local::now().with_timezone(zone))
+            .map(|time| time.with_timezone(&Local))
+            .ok_or(Error::Iso(error.shifted(offset))),
+    }
+}
+
+/// Splits an ISO 8601 interval on its slashes, except those within a bracketed timezone, as in
+/// `Europe/Paris`.
+fn split_interval(iso: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let (mut bracketed, mut from) = (false, 0);
+    for (i, c) in iso.char_indices() {
+        match c {
+            '[' => bracketed = true,
+            ']' => bracketed = false,
+            '/' if !bracketed => {
+                tokens.push(&iso[from..i]);
+                from = i + 1;
+            },
+            _ => {},
+        }
+    }
+    tokens.push(&iso[from..]);
+    tokens
+}
+
+/// Returns true if the characters of the query, other than spaces, appear in the text in order,
+/// ignoring case, as "hspr" does in "history paper".
+fn fuzzy(query: &str, text: &str) -> bool {
+    let mut text = text.chars().flat_map(char::to_lowercase);
+    query.chars().filter(|c| !c.is_whitespace()).flat_map(char::to_lowercase).all(|c| text.any(|t| t == c))
+}
+
+/// Attempts to parse an ISO 8601 duration from `token`, a slice of the insertion `buffer`.
+fn duration_from_iso(buffer: &str, token: &str) -> Result<IsoDuration, Error> {
+    let offset = token.as_ptr() as usize - buffer.as_ptr() as usize;
+    iso::parse_duration(token).map_err(|error| Error::Iso(error.shifted(offset)))
+}
+
+/// The start and end of an interval, and which side of it was entered as a duration, if any.
+type ResolvedInterval = (DateTime<Local>, DateTime<Local>, Option<Span>);
+
+/// Resolves the two halves of an ISO 8601 interval, either of which may be a duration, into a
+/// start and an end. Durations follow the clock of the given timezone.
+fn interval_from_iso(buffer: &str, start: &str, end: &str, zone: &mut Zone) -> Result<ResolvedInterval, Error> {
+    match (start.starts_with('P'), end.starts_with('P')) {
+        (false, false) => {
+            let start = datetime_from_iso(buffer, start, zone)?;
+            Ok((start, datetime_from_iso(buffer, end, zone)?, None))
+        },
+        (false, true) => {
+            let start = datetime_from_iso(buffer, start, zone)?;
+            let duration = duration_from_iso(buffer, end)?;
+            let end = duration.add_to(start.with_timezone(zone)).ok_or(Error::InvalidTime)?;
+            Ok((start, end.with_timezone(&Local), Some(Span::After(duration))))
+        },
+        (true, false) => {
+            let end = datetime_from_iso(buffer, end, zone)?;
+            let duration = duration_from_iso(buffer, start)?;
+            let start = duration.subtract_from(end.with_timezone(zone)).ok_or(Error::InvalidTime)?;
+            Ok((start.with_timezone(&Local), end, Some(Span::Before(duration))))
+        },
+        (true, true) => Err(Error::InvalidIso),
+    }
+}
+
 impl Default for State {
     fn default() -> Self {
         State {
+            backups: Vec::new(),
+            backups_state: ListState::default(),
             buffer: String::new(),
             cursor_offset: 0,
+            dirty: false,
+            editing: None,
             focus: Focus::None,
             intervals: Vec::new(),
             intervals_offset: 0,
             intervals_state: TableState::default(),
             last_error: None,
+            query: String::new(),
+            query_filter: None,
             timed: Vec::new(),
             timed_offset: 0,
             timed_state: TableState::default(),
@@ -272,3 +717,59 @@ impl Default for State {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+    use super::*;
+
+    #[test]
+    fn yanking_all_keeps_skipped_and_replaced_occurrences() {
+        let start = Local.ymd(2100, 1, 4).and_hms(9, 0, 0);
+        let mut event = Event {
+            start: Some(start),
+            interval: Interval::RepIndefinite(start + Duration::weeks(1)),
+            exceptions: vec![start + Duration::weeks(2)],
+            overrides: vec![Override {
+                occurrence: start + Duration::weeks(3),
+                start: start + Duration::weeks(3) + Duration::hours(1),
+                description: String::from("late standup"),
+            }],
+            ..Event::new(String::from("standup"))
+        };
+        event.infer_rule();
+        let id = event.id;
+        let mut s = State::default();
+        s.replace_events(vec![event]);
+        s.focus(Focus::Timed);
+        s.yank_selected();
+        s.choose_scope(Scope::All);
+        s.buffer = s.buffer.replace("standup", "daily standup");
+        assert_eq!(s.add_event_from_buffer().ok(), Some(id));
+        let event = s.find(id).unwrap();
+        assert_eq!(event.description, "daily standup");
+        assert_eq!(event.exceptions, [start + Duration::weeks(2)]);
+        assert_eq!(event.overrides.len(), 1);
+    }
+
+    #[test]
+    fn deleting_an_occurrence_or_all() {
+        let start = Local.ymd(2100, 1, 4).and_hms(9, 0, 0);
+        let event = Event {
+            start: Some(start),
+            interval: Interval::RepIndefinite(start + Duration::weeks(1)),
+            ..Event::new(String::from("standup"))
+        };
+        let id = event.id;
+        let mut s = State::default();
+        s.replace_events(vec![event]);
+        s.focus(Focus::Timed);
+        s.delete_selected();
+        s.choose_scope(Scope::Occurrence);
+        assert_eq!(s.find(id).and_then(|event| event.start), Some(start + Duration::weeks(1)));
+        // all occurrences are the current one and those after it, past ones never being kept
+        s.delete_selected();
+        s.choose_scope(Scope::All);
+        assert!(s.find(id).is_none());
+    }
+}
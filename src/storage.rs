@@ -0,0 +1,143 @@
+//! Contains the interface through which events are kept between sessions.
+//!
+//! A Storage loads and saves every event at once, and can also find the events within a range of
+//! dates or still outstanding, and insert, update and delete single events. Stores that cannot do
+//! the latter any cheaper get them for free by loading, changing and saving everything. Single
+//! events are recognized by their id, since events compare equal by their time and description
+//! alone.
+
+pub mod json;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+use chrono::{DateTime, Local};
+use crate::{Event, Interval};
+use std::{fmt, io};
+use uuid::Uuid;
+
+/// Returned when events cannot be loaded or saved.
+#[derive(Debug)]
+pub enum StorageError {
+    /// The stored events could not be understood.
+    Corrupt(serde_json::Error),
+    /// The store exists but holds nothing at all, not even an empty list of events.
+    Empty,
+    Io(io::Error),
+    /// The store does not exist yet.
+    Missing,
+    /// No event with the id to update or delete is in the store.
+    NotFound,
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+    /// The store was written by a newer version of clamendar, whose layout is of this version.
+    Version(u64),
+}
+
+/// A place to keep events.
+pub trait Storage {
+    /// Reads every event.
+    fn load(&mut self) -> Result<Vec<Event>, StorageError>;
+
+    /// Replaces every event with the given ones.
+    fn save(&mut self, events: &[&Event]) -> Result<(), StorageError>;
+
+    /// Reads the events with an occurrence in [from, to), as in `Event::occurrences`, with their
+    /// rules inferred where missing, as by `Event::infer_rule`, since that may move occurrences.
+    fn between(&mut self, from: DateTime<Local>, to: DateTime<Local>) -> Result<Vec<Event>, StorageError> {
+        let mut events = self.load()?;
+        events.iter_mut().for_each(Event::infer_rule);
+        events.retain(|event| event.occurrences(from, to).next().is_some());
+        Ok(events)
+    }
+
+    /// Reads the events that stay due until deleted: single events before `time`, which are
+    /// overdue, and untimed events.
+    fn outstanding(&mut self, time: DateTime<Local>) -> Result<Vec<Event>, StorageError> {
+        let mut events = self.load()?;
+        events.retain(|event| is_outstanding(event, time));
+        Ok(events)
+    }
+
+    /// Removes the event with the given id.
+    fn delete(&mut self, id: Uuid) -> Result<(), StorageError> {
+        let mut events = self.load()?;
+        let index = position(&events, id)?;
+        events.remove(index);
+        self.save(&events.iter().collect::<Vec<&Event>>())
+    }
+
+    /// Adds one event.
+    fn insert(&mut self, event: &Event) -> Result<(), StorageError> {
+        let events = self.load()?;
+        let mut events: Vec<&Event> = events.iter().collect();
+        events.push(event);
+        self.save(&events)
+    }
+
+    /// Replaces the event with the same id as `event`.
+    fn update(&mut self, event: &Event) -> Result<(), StorageError> {
+        let events = self.load()?;
+        let index = position(&events, event.id)?;
+        let mut events: Vec<&Event> = events.iter().collect();
+        events[index] = event;
+        self.save(&events)
+    }
+}
+
+/// Keeps events in memory only, for trying things out and for tests.
+#[derive(Default)]
+pub struct Memory {
+    // events are kept serialized, as Event cannot be cloned
+    events: Vec<String>,
+}
+
+impl Storage for Memory {
+    fn load(&mut self) -> Result<Vec<Event>, StorageError> {
+        self.events
+            .iter()
+            .map(|event| serde_json::from_str(event).map_err(StorageError::Corrupt))
+            .collect()
+    }
+
+    fn save(&mut self, events: &[&Event]) -> Result<(), StorageError> {
+        self.events = events
+            .iter()
+            .map(|event| serde_json::to_string(event).map_err(StorageError::Corrupt))
+            .collect::<Result<_, _>>()?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageError::Corrupt(error) => write!(f, "the stored events are corrupt: {}", error),
+            StorageError::Empty => write!(f, "the store is empty"),
+            StorageError::Io(error) => write!(f, "i/o error: {}", error),
+            StorageError::Missing => write!(f, "the store does not exist"),
+            StorageError::NotFound => write!(f, "the event is not in the store"),
+            #[cfg(feature = "sqlite")]
+            StorageError::Sqlite(error) => write!(f, "database error: {}", error),
+            StorageError::Version(version) => write!(f, "the store is from a newer version of clamendar (version {})", version),
+        }
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(error: io::Error) -> Self {
+        StorageError::Io(error)
+    }
+}
+
+/// Returns true if the event stays due until deleted as of `time`, as for `Storage::outstanding`.
+pub(crate) fn is_outstanding(event: &Event, time: DateTime<Local>) -> bool {
+    match event.start {
+        Some(start) => matches!(event.interval, Interval::None) && start < time,
+        None => true,
+    }
+}
+
+/// Finds an event among others by its id.
+fn position(events: &[Event], id: Uuid) -> Result<usize, StorageError> {
+    events.iter().position(|event| event.id == id).ok_or(StorageError::NotFound)
+}
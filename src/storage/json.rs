@@ -0,0 +1,120 @@
+//! Contains the storage of events in a single JSON file.
+//!
+//! The file is an envelope holding the version of its layout along with the events, as in
+//! `{"version": 2, "events": [...]}`. Files written before versioning hold a bare array of events
+//! and count as version 0. A file of an older version is brought up to date one migration at a
+//! time before its events are deserialized, so that only the current layout needs to be
+//! understood by `Event` itself.
+
+use crate::{storage::{Storage, StorageError}, Event};
+use serde_json::{json, Value};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+/// The version of the layout written by this build. Files of newer versions are not read.
+pub const VERSION: u64 = 2;
+
+/// A migration rewrites a file as read in one version into the layout of the next.
+type Migration = fn(Value) -> Result<Value, StorageError>;
+
+/// The migration at index `n` takes a file from version `n` to version `n + 1`.
+const MIGRATIONS: [Migration; VERSION as usize] = [envelop, identify];
+
+/// Events kept in a JSON file.
+pub struct JsonFile {
+    pub path: PathBuf,
+}
+
+impl JsonFile {
+    pub fn new(path: &Path) -> Self {
+        JsonFile { path: path.to_path_buf() }
+    }
+
+    /// Reads every event, migrating them in memory from an older version of the file if needed.
+    /// Also returns the version the file was in.
+    pub fn load_versioned(&self) -> Result<(Vec<Event>, u64), StorageError> {
+        let file = match fs::read_to_string(&self.path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Err(StorageError::Missing),
+            Err(error) => return Err(StorageError::Io(error)),
+        };
+        if file.trim().is_empty() { return Err(StorageError::Empty); }
+        decode(&file)
+    }
+}
+
+impl Storage for JsonFile {
+    fn load(&mut self) -> Result<Vec<Event>, StorageError> {
+        self.load_versioned().map(|(events, _)| events)
+    }
+
+    /// The file is replaced only once the new contents are safely on disk, so that a crash or a
+    /// full disk never leaves it half-written.
+    fn save(&mut self, events: &[&Event]) -> Result<(), StorageError> {
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(".tmp");
+        let temporary = self.path.with_file_name(name);
+        let mut writer = fs::File::create(&temporary)?;
+        writer.write_all(&encode(events)?)?;
+        writer.sync_all()?;
+        fs::rename(&temporary, &self.path)?;
+        // the rename itself only lasts once the directory is synced too
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::File::open(parent)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads events from the contents of an events file of any version up to the current one. Also
+/// returns the version the file was in.
+fn decode(contents: &str) -> Result<(Vec<Event>, u64), StorageError> {
+    let mut file: Value = serde_json::from_str(contents).map_err(StorageError::Corrupt)?;
+    let version = version_of(&file)?;
+    if version > VERSION { return Err(StorageError::Version(version)); }
+    for migration in &MIGRATIONS[version as usize..] {
+        file = migration(file)?;
+    }
+    let events = match file {
+        Value::Object(mut object) => object.remove("events").unwrap_or(Value::Null),
+        _ => Value::Null,
+    };
+    let events = serde_json::from_value(events).map_err(StorageError::Corrupt)?;
+    Ok((events, version))
+}
+
+/// Writes events in the current layout.
+pub fn encode(events: &[&Event]) -> Result<Vec<u8>, StorageError> {
+    serde_json::to_vec(&json!({ "version": VERSION, "events": events })).map_err(StorageError::Corrupt)
+}
+
+/// Version 0 to 1: wraps the bare array of events in an envelope.
+fn envelop(file: Value) -> Result<Value, StorageError> {
+    Ok(json!({ "version": 1, "events": file }))
+}
+
+/// Version 1 to 2: gives every event an id.
+fn identify(mut file: Value) -> Result<Value, StorageError> {
+    if let Some(Value::Array(events)) = file.get_mut("events") {
+        for event in events.iter_mut().filter_map(Value::as_object_mut) {
+            event.entry("id").or_insert_with(|| json!(Uuid::new_v4()));
+        }
+    }
+    file["version"] = json!(2);
+    Ok(file)
+}
+
+/// Returns the version of a file, which is 0 for a bare array.
+fn version_of(file: &Value) -> Result<u64, StorageError> {
+    match file {
+        Value::Array(_) => Ok(0),
+        Value::Object(object) => object.get("version")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| StorageError::Corrupt(serde::de::Error::missing_field("version"))),
+        _ => Err(StorageError::Corrupt(serde::de::Error::custom("expected an object or an array of events"))),
+    }
+}
@@ -0,0 +1,240 @@
+//! Contains the storage of events in an SQLite database.
+//!
+//! Each event is a row holding the event as JSON, along with its id and the earliest and latest
+//! times at which it happens, all indexed, so that events within a range of dates can be found without
+//! reading the rest. Times are stored in UTC as `2021-12-31T23:59:59Z`, which sorts
+//! chronologically as text. Changing a single event only touches its own row.
+
+use chrono::{DateTime, Local, TimeZone, Utc};
+use crate::{storage::{self, Storage, StorageError}, Event, Interval};
+use rusqlite::{params, Connection, Transaction};
+use std::path::Path;
+use uuid::Uuid;
+
+/// The version of the database layout written by this build, kept as the `user_version` of the
+/// database. Databases of newer versions are not read.
+pub const VERSION: u64 = 2;
+
+/// The layout of version 1, which later versions migrate from.
+const SCHEMA: &str = r#"
+    CREATE TABLE IF NOT EXISTS events (
+        id INTEGER PRIMARY KEY,
+        start TEXT,
+        "end" TEXT,
+        event TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS events_start ON events (start);
+    CREATE INDEX IF NOT EXISTS events_end ON events ("end");
+"#;
+
+/// Events kept in an SQLite database.
+pub struct Sqlite {
+    connection: Connection,
+}
+
+/// The columns of an event's row besides its row id: the id of the event, its earliest start, its
+/// latest end, and the event itself as JSON.
+type Row = (String, Option<String>, Option<String>, String);
+
+impl Sqlite {
+    /// Opens the database at `path`, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self, StorageError> {
+        let mut connection = Connection::open(path)?;
+        let version: u64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version > VERSION { return Err(StorageError::Version(version)); }
+        // opening an existing database must not write to it, or other processes would see it change
+        if version < VERSION {
+            let transaction = connection.transaction()?;
+            if version < 1 { transaction.execute_batch(SCHEMA)?; }
+            if version < 2 { identify(&transaction)?; }
+            transaction.execute_batch(&format!("PRAGMA user_version = {}", VERSION))?;
+            transaction.commit()?;
+        }
+        Ok(Sqlite { connection })
+    }
+}
+
+impl Storage for Sqlite {
+    fn load(&mut self) -> Result<Vec<Event>, StorageError> {
+        let mut statement = self.connection.prepare("SELECT event FROM events ORDER BY id")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        rows.map(|json| serde_json::from_str(&json?).map_err(StorageError::Corrupt)).collect()
+    }
+
+    /// Rows are only added and removed where the events differ from those stored.
+    fn save(&mut self, events: &[&Event]) -> Result<(), StorageError> {
+        let mut rows = events.iter().map(|event| row(event)).collect::<Result<Vec<Row>, _>>()?;
+        let transaction = self.connection.transaction()?;
+        {
+            let mut existing = transaction.prepare("SELECT id, event FROM events")?;
+            let existing = existing
+                .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            for (id, json) in existing {
+                match rows.iter().position(|(_, _, _, other)| *other == json) {
+                    Some(index) => { rows.swap_remove(index); },
+                    None => { transaction.execute("DELETE FROM events WHERE id = ?1", [id])?; },
+                }
+            }
+            let mut insert = transaction.prepare(r#"INSERT INTO events (uuid, start, "end", event) VALUES (?1, ?2, ?3, ?4)"#)?;
+            for (uuid, start, end, json) in rows {
+                insert.execute(params![uuid, start, end, json])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Only rows whose earliest start and latest end overlap the range are read.
+    fn between(&mut self, from: DateTime<Local>, to: DateTime<Local>) -> Result<Vec<Event>, StorageError> {
+        let mut statement = self.connection.prepare(
+            r#"SELECT event FROM events WHERE start < ?1 AND ("end" IS NULL OR "end" >= ?2) ORDER BY start"#
+        )?;
+        let rows = statement.query_map([stamp(to), stamp(from)], |row| row.get::<_, String>(0))?;
+        let mut events = Vec::new();
+        for json in rows {
+            let mut event: Event = serde_json::from_str(&json?).map_err(StorageError::Corrupt)?;
+            event.infer_rule();
+            if event.occurrences(from, to).next().is_some() { events.push(event); }
+        }
+        Ok(events)
+    }
+
+    /// Only untimed rows and those of single events before the time are read; a single event
+    /// ends where it starts.
+    fn outstanding(&mut self, time: DateTime<Local>) -> Result<Vec<Event>, StorageError> {
+        let mut statement = self.connection.prepare(
+            r#"SELECT event FROM events WHERE start IS NULL OR (start < ?1 AND "end" = start) ORDER BY id"#
+        )?;
+        let rows = statement.query_map([stamp(time)], |row| row.get::<_, String>(0))?;
+        let mut events = Vec::new();
+        for json in rows {
+            let event: Event = serde_json::from_str(&json?).map_err(StorageError::Corrupt)?;
+            if storage::is_outstanding(&event, time) { events.push(event); }
+        }
+        Ok(events)
+    }
+
+    fn delete(&mut self, id: Uuid) -> Result<(), StorageError> {
+        match self.connection.execute("DELETE FROM events WHERE uuid = ?1", [id.to_string()])? {
+            0 => Err(StorageError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    fn insert(&mut self, event: &Event) -> Result<(), StorageError> {
+        let (uuid, start, end, json) = row(event)?;
+        self.connection.execute(
+            r#"INSERT INTO events (uuid, start, "end", event) VALUES (?1, ?2, ?3, ?4)"#,
+            params![uuid, start, end, json],
+        )?;
+        Ok(())
+    }
+
+    fn update(&mut self, event: &Event) -> Result<(), StorageError> {
+        let (uuid, start, end, json) = row(event)?;
+        let updated = self.connection.execute(
+            r#"UPDATE events SET start = ?1, "end" = ?2, event = ?3 WHERE uuid = ?4"#,
+            params![start, end, json, uuid],
+        )?;
+        match updated {
+            0 => Err(StorageError::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(error: rusqlite::Error) -> Self {
+        StorageError::Sqlite(error)
+    }
+}
+
+/// Version 1 to 2: adds a column for the id of each event, giving every event one.
+fn identify(transaction: &Transaction) -> Result<(), StorageError> {
+    transaction.execute_batch("ALTER TABLE events ADD COLUMN uuid TEXT; CREATE UNIQUE INDEX events_uuid ON events (uuid);")?;
+    let rows = {
+        let mut statement = transaction.prepare("SELECT id, event FROM events")?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+        rows.collect::<Result<Vec<_>, _>>()?
+    };
+    for (id, json) in rows {
+        // the event is given an id as it is read
+        let event: Event = serde_json::from_str(&json).map_err(StorageError::Corrupt)?;
+        let (uuid, _, _, json) = row(&event)?;
+        transaction.execute("UPDATE events SET uuid = ?1, event = ?2 WHERE id = ?3", params![uuid, json, id])?;
+    }
+    Ok(())
+}
+
+/// Works out the columns of an event's row. An untimed event has neither a start nor an end, and
+/// one repeating forever has no end.
+fn row(event: &Event) -> Result<Row, StorageError> {
+    let json = serde_json::to_string(event).map_err(StorageError::Corrupt)?;
+    let uuid = event.id.to_string();
+    let start = match event.start {
+        Some(start) => start,
+        None => return Ok((uuid, None, None, json)),
+    };
+    // overrides may move occurrences before the start or after the last one
+    let moved = event.overrides.iter().map(|replacement| replacement.start);
+    let first = moved.clone().fold(start, DateTime::min);
+    let last = match event.interval {
+        Interval::None => Some(start),
+        Interval::Standard(end) => Some(end),
+        Interval::RepDefinite { .. } => {
+            // the occurrences are those between() will see, with the rule inferred if missing
+            let mut inferred: Event = serde_json::from_str(&json).map_err(StorageError::Corrupt)?;
+            inferred.infer_rule();
+            let never = Local.ymd(9999, 1, 1).and_hms(0, 0, 0);
+            Some(inferred.occurrences(start, never).last().map_or(start, |(_, end)| end))
+        },
+        Interval::RepIndefinite(_) => None,
+    };
+    let last = last.map(|last| moved.fold(last, DateTime::max));
+    Ok((uuid, Some(stamp(first)), last.map(stamp), json))
+}
+
+fn stamp(time: DateTime<Local>) -> String {
+    time.with_timezone(&Utc).format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use crate::storage::Memory;
+    use super::*;
+
+    fn at(description: &str, start: DateTime<Local>, interval: Interval) -> Event {
+        Event { start: Some(start), interval, ..Event::new(description.to_string()) }
+    }
+
+    fn descriptions(mut events: Vec<Event>) -> Vec<String> {
+        events.sort_by(|a, b| a.description.cmp(&b.description));
+        events.into_iter().map(|event| event.description).collect()
+    }
+
+    #[test]
+    fn ranges_and_outstanding_events() {
+        let mut sqlite = Sqlite::open(Path::new(":memory:")).unwrap();
+        let from = Local.ymd(2021, 6, 1).and_hms(0, 0, 0);
+        let to = from + Duration::days(7);
+        let events = [
+            at("before", from - Duration::days(2), Interval::None),
+            at("within", from + Duration::days(3), Interval::None),
+            at("after", to, Interval::None),
+            at("spanning", from - Duration::days(1), Interval::Standard(from + Duration::hours(1))),
+            at("weekly", from - Duration::days(30), Interval::RepIndefinite(from - Duration::days(23))),
+            at("ended", from - Duration::days(30), Interval::RepDefinite { occurrences: 2, end: from - Duration::days(29) }),
+            Event::new(String::from("untimed")),
+        ];
+        sqlite.save(&events.iter().collect::<Vec<&Event>>()).unwrap();
+        assert_eq!(descriptions(sqlite.between(from, to).unwrap()), ["spanning", "weekly", "within"]);
+        assert_eq!(descriptions(sqlite.outstanding(from).unwrap()), ["before", "untimed"]);
+        // the same as the stores that load everything
+        let mut memory = Memory::default();
+        memory.save(&events.iter().collect::<Vec<&Event>>()).unwrap();
+        assert_eq!(descriptions(memory.between(from, to).unwrap()), ["spanning", "weekly", "within"]);
+        assert_eq!(descriptions(memory.outstanding(from).unwrap()), ["before", "untimed"]);
+    }
+}
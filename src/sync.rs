@@ -0,0 +1,256 @@
+//! Contains the coordination between several clamendar processes sharing one events file.
+//!
+//! Reading and writing the file happens under an advisory lock on a `.lock` file beside it, which
+//! is only held for as long as that takes. Each process also remembers what the file looked like
+//! when it last read or wrote it, so that it notices when another process changed it in the
+//! meantime. It then refuses to overwrite the file until told to merge or to reload.
+
+use clamendar::{storage::{Storage, StorageError}, Event};
+use crate::{error::Error, state::State};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fs::{self, File},
+    hash::{Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+/// The events file as this process last read or wrote it, along with the storage through which it
+/// is read and written.
+pub struct Snapshot {
+    /// The events in the file by id, each serialized on its own, to tell which ones changed on
+    /// either side. Events compare equal by their time alone, so they are compared in this form
+    /// instead.
+    events: HashMap<Uuid, String>,
+    /// A hash of the contents of the file, or None if there was no file.
+    fingerprint: Option<u64>,
+    path: PathBuf,
+    storage: Box<dyn Storage>,
+}
+
+/// Holds the lock on an events file until dropped.
+pub struct Lock(File);
+
+impl Snapshot {
+    /// Remembers the events file as it is now, given the events it holds.
+    fn refresh(&mut self, events: &[&Event]) -> Result<(), Error> {
+        self.events = to_strings(events)?.into_iter().collect();
+        self.fingerprint = fingerprint(&self.path)?;
+        Ok(())
+    }
+
+    /// Returns true if the file is no longer as remembered.
+    fn is_stale(&self) -> Result<bool, Error> {
+        Ok(fingerprint(&self.path)? != self.fingerprint)
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
+}
+
+/// Reloads the events if the file changed on disk since the snapshot was taken, returning true
+/// if it did. Unsaved changes are never discarded; Error::Conflict is returned instead.
+pub fn follow(snapshot: &mut Snapshot, s: &mut State) -> Result<bool, Error> {
+    let _lock = lock(&snapshot.path)?;
+    if !snapshot.is_stale()? { return Ok(false); }
+    if s.dirty { return Err(Error::Conflict); }
+    let events = read(snapshot.storage.as_mut())?;
+    snapshot.refresh(&events.iter().collect::<Vec<&Event>>())?;
+    s.replace_events(events);
+    Ok(true)
+}
+
+/// Waits for exclusive access to the events file at `path`.
+pub fn lock(path: &Path) -> Result<Lock, Error> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".lock");
+    let file = File::create(path.with_file_name(name))?;
+    file.lock()?;
+    Ok(Lock(file))
+}
+
+/// Combines the events on disk with those in the interface event by event, keeping the changes
+/// made on both sides since the snapshot was taken, then saves the result. The same change made on
+/// both sides is kept once, and a change on one side wins over a deletion on the other. An event
+/// changed differently on both sides is kept in both versions, this side's under a new id.
+pub fn merge(snapshot: &mut Snapshot, s: &mut State) -> Result<(), Error> {
+    let _lock = lock(&snapshot.path)?;
+    let theirs = to_strings(&read(snapshot.storage.as_mut())?.iter().collect::<Vec<&Event>>())?;
+    let ours = to_strings(&s.events())?;
+    let base = &snapshot.events;
+    let (their_map, our_map): (HashMap<_, _>, HashMap<_, _>) =
+        (theirs.iter().cloned().collect(), ours.iter().cloned().collect());
+    // start from theirs, taking this side's version of the events only this side changed and
+    // dropping those this side deleted and the other left alone
+    let mut events = Vec::new();
+    for (id, event) in &theirs {
+        let kept = match (base.get(id), our_map.get(id)) {
+            (Some(original), None) if original == event => continue,
+            (Some(original), Some(mine)) if original == event => mine,
+            _ => event,
+        };
+        events.push(serde_json::from_str::<Event>(kept)?);
+    }
+    // then add what the other side lacks, unless it deleted the event and this side left it alone,
+    // and this side's version of what both changed differently
+    for (id, mine) in ours {
+        let skip = match their_map.get(&id) {
+            Some(event) => *event == mine || base.get(&id).is_some_and(|original| *original == mine || original == event),
+            None => base.get(&id) == Some(&mine),
+        };
+        if skip { continue; }
+        let mut event: Event = serde_json::from_str(&mine)?;
+        if their_map.contains_key(&id) { event.id = Uuid::new_v4(); }
+        events.push(event);
+    }
+    let references: Vec<&Event> = events.iter().collect();
+    snapshot.storage.save(&references)?;
+    snapshot.refresh(&references)?;
+    s.replace_events(events);
+    s.dirty = false;
+    Ok(())
+}
+
+/// Replaces the events in the interface with those on disk, discarding unsaved changes.
+pub fn reload(snapshot: &mut Snapshot, s: &mut State) -> Result<(), Error> {
+    let _lock = lock(&snapshot.path)?;
+    let events = read(snapshot.storage.as_mut())?;
+    snapshot.refresh(&events.iter().collect::<Vec<&Event>>())?;
+    s.replace_events(events);
+    s.dirty = false;
+    Ok(())
+}
+
+/// Saves the events in the interface, unless the file changed on disk since the snapshot was
+/// taken, in which case Error::Conflict is returned and nothing is written.
+pub fn save(snapshot: &mut Snapshot, s: &State) -> Result<(), Error> {
+    let _lock = lock(&snapshot.path)?;
+    if snapshot.is_stale()? { return Err(Error::Conflict); }
+    let events = s.events();
+    snapshot.storage.save(&events)?;
+    snapshot.refresh(&events)?;
+    Ok(())
+}
+
+/// Reads the events file at `path` through `storage` and remembers it as it was read.
+pub fn start(path: &Path, mut storage: Box<dyn Storage>) -> Result<(Vec<Event>, Snapshot), Error> {
+    let _lock = lock(path)?;
+    let events = read(storage.as_mut())?;
+    let mut snapshot = Snapshot { events: HashMap::new(), fingerprint: None, path: path.to_path_buf(), storage };
+    snapshot.refresh(&events.iter().collect::<Vec<&Event>>())?;
+    Ok((events, snapshot))
+}
+
+/// Hashes the contents of the file, or returns None if there is no file.
+fn fingerprint(path: &Path) -> Result<Option<u64>, Error> {
+    match fs::read(path) {
+        Ok(contents) => {
+            let mut hasher = DefaultHasher::new();
+            contents.hash(&mut hasher);
+            Ok(Some(hasher.finish()))
+        },
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(Error::Io(error)),
+    }
+}
+
+/// Reads every event, of which a blank file holds none.
+pub fn read(storage: &mut dyn Storage) -> Result<Vec<Event>, Error> {
+    found(storage.load())
+}
+
+/// Takes the events read from a store, of which a blank file holds none.
+pub fn found(result: Result<Vec<Event>, StorageError>) -> Result<Vec<Event>, Error> {
+    match result {
+        Err(StorageError::Empty) => Ok(Vec::new()),
+        result => Ok(result?),
+    }
+}
+
+/// Serializes each event on its own, alongside its id, keeping their order.
+fn to_strings(events: &[&Event]) -> Result<Vec<(Uuid, String)>, Error> {
+    Ok(events.iter().map(|event| Ok((event.id, serde_json::to_string(event)?))).collect::<Result<_, serde_json::Error>>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use clamendar::storage::Memory;
+    use super::*;
+
+    fn untimed(description: &str) -> Event {
+        Event::new(description.to_string())
+    }
+
+    /// Starts a session on a store holding the given events, returning it along with the interface
+    /// state it loaded.
+    fn session(name: &str, events: &[&Event]) -> (Snapshot, State) {
+        let mut storage = Memory::default();
+        storage.save(events).unwrap();
+        let path = std::env::temp_dir().join(format!("clamendar-sync-{}-{}", name, std::process::id()));
+        let (events, snapshot) = start(&path, Box::new(storage)).unwrap();
+        let mut s = State::default();
+        s.replace_events(events);
+        (snapshot, s)
+    }
+
+    /// Stands in for another process changing the store behind the snapshot's back.
+    fn change_theirs(snapshot: &mut Snapshot, change: impl FnOnce(&mut Vec<Event>)) {
+        let mut events = read(snapshot.storage.as_mut()).unwrap();
+        change(&mut events);
+        snapshot.storage.save(&events.iter().collect::<Vec<&Event>>()).unwrap();
+    }
+
+    fn descriptions(s: &State) -> Vec<String> {
+        let mut descriptions: Vec<String> = s.events().iter().map(|event| event.description.clone()).collect();
+        descriptions.sort();
+        descriptions
+    }
+
+    #[test]
+    fn same_change_is_kept_once() {
+        let event = untimed("draft");
+        let (mut snapshot, mut s) = session("same", &[&event]);
+        change_theirs(&mut snapshot, |events| events[0].description = String::from("final"));
+        s.untimed[0].description = String::from("final");
+        merge(&mut snapshot, &mut s).unwrap();
+        assert_eq!(descriptions(&s), ["final"]);
+        assert_eq!(s.events()[0].id, event.id);
+    }
+
+    #[test]
+    fn different_changes_are_both_kept() {
+        let event = untimed("draft");
+        let (mut snapshot, mut s) = session("different", &[&event]);
+        change_theirs(&mut snapshot, |events| events[0].description = String::from("theirs"));
+        s.untimed[0].description = String::from("ours");
+        merge(&mut snapshot, &mut s).unwrap();
+        assert_eq!(descriptions(&s), ["ours", "theirs"]);
+        let events = s.events();
+        assert_ne!(events[0].id, events[1].id);
+        assert!(events.iter().any(|kept| kept.id == event.id && kept.description == "theirs"));
+    }
+
+    #[test]
+    fn changes_on_one_side_are_taken() {
+        let (kept, changed, deleted, ignored) = (untimed("kept"), untimed("changed"), untimed("deleted"), untimed("ignored"));
+        let (mut snapshot, mut s) = session("one-side", &[&kept, &changed, &deleted, &ignored]);
+        // the other side adds an event and deletes one this side left alone
+        change_theirs(&mut snapshot, |events| {
+            events.retain(|event| event.description != "ignored");
+            events.push(untimed("added by them"));
+        });
+        // this side changes one, deletes another and adds its own
+        s.remove(deleted.id);
+        s.untimed.iter_mut().find(|event| event.id == changed.id).unwrap().description = String::from("changed by us");
+        s.untimed.push(untimed("added by us"));
+        merge(&mut snapshot, &mut s).unwrap();
+        assert_eq!(descriptions(&s), ["added by them", "added by us", "changed by us", "kept"]);
+        // merging again with nothing new changes nothing
+        merge(&mut snapshot, &mut s).unwrap();
+        assert_eq!(descriptions(&s), ["added by them", "added by us", "changed by us", "kept"]);
+    }
+}
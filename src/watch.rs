@@ -0,0 +1,39 @@
+//! Contains the watch kept on the events file for changes made by other programs.
+//!
+//! The directory holding the file is watched rather than the file itself, since the file is
+//! replaced rather than written in place, both by clamendar and by most other programs.
+
+use crate::error::Error;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher as _};
+use std::{
+    path::Path,
+    sync::mpsc::{self, Receiver},
+};
+
+/// Notices when the events file is created, written, replaced or removed.
+pub struct Watcher {
+    changes: Receiver<()>,
+    // dropping the watcher stops the watch
+    _watcher: RecommendedWatcher,
+}
+
+impl Watcher {
+    /// Starts watching the events file at `path`.
+    pub fn new(path: &Path) -> Result<Self, Error> {
+        let name = path.file_name().unwrap_or_default().to_os_string();
+        let (sender, changes) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+            if let Ok(event) = result {
+                if event.paths.iter().any(|path| path.file_name() == Some(&name)) { let _ = sender.send(()); }
+            }
+        })?;
+        let dir = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        Ok(Watcher { changes, _watcher: watcher })
+    }
+
+    /// Returns true if the file was touched since this was last asked.
+    pub fn changed(&self) -> bool {
+        self.changes.try_iter().count() > 0
+    }
+}
@@ -0,0 +1,150 @@
+//! Contains the timezone an event is entered in.
+//!
+//! Events in a named IANA timezone, such as `Europe/Paris`, or at a fixed offset from UTC happen at
+//! the same instant wherever they are viewed from, and repeat by the clock of their own timezone.
+//! Floating events happen at the same wall-clock time in whichever timezone the viewer is in.
+
+use chrono::{
+    FixedOffset,
+    Local,
+    LocalResult,
+    NaiveDate,
+    NaiveDateTime,
+    Offset,
+    TimeZone,
+};
+use chrono_tz::Tz;
+use crate::iso;
+use serde::{Deserialize, Serialize};
+use std::{convert::TryFrom, fmt, str::FromStr};
+
+/// The timezone of an event.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Zone {
+    /// Follows the Local timezone of whoever is viewing the event.
+    #[default]
+    Floating,
+    Fixed(FixedOffset),
+    Named(Tz),
+}
+
+/// The offset of a Zone at some instant, which remembers the zone it came from.
+#[derive(Clone, Copy, Debug)]
+pub struct ZoneOffset {
+    zone: Zone,
+    fixed: FixedOffset,
+}
+
+/// Returned when a string names neither a known IANA timezone nor an offset from UTC.
+#[derive(Debug, PartialEq)]
+pub struct ParseZoneError(pub String);
+
+impl Zone {
+    pub fn is_floating(&self) -> bool {
+        matches!(self, Zone::Floating)
+    }
+}
+
+// Zones are written as "floating", an offset such as "+02:00", or an IANA name.
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Zone::Floating => write!(f, "floating"),
+            Zone::Fixed(offset) => write!(f, "{}", offset),
+            Zone::Named(tz) => write!(f, "{}", tz.name()),
+        }
+    }
+}
+
+impl FromStr for Zone {
+    type Err = ParseZoneError;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "floating" => Ok(Zone::Floating),
+            _ => match string.parse::<Tz>() {
+                Ok(tz) => Ok(Zone::Named(tz)),
+                Err(_) => iso::parse_offset(string)
+                    .map(Zone::Fixed)
+                    .map_err(|_| ParseZoneError(string.to_string())),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for Zone {
+    type Error = ParseZoneError;
+
+    fn try_from(string: String) -> Result<Self, Self::Error> {
+        string.parse()
+    }
+}
+
+impl From<Zone> for String {
+    fn from(zone: Zone) -> Self {
+        zone.to_string()
+    }
+}
+
+impl fmt::Display for ParseZoneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown timezone: {}", self.0)
+    }
+}
+
+impl Offset for ZoneOffset {
+    fn fix(&self) -> FixedOffset {
+        self.fixed
+    }
+}
+
+impl fmt::Display for ZoneOffset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.fixed)
+    }
+}
+
+impl TimeZone for Zone {
+    type Offset = ZoneOffset;
+
+    fn from_offset(offset: &ZoneOffset) -> Self {
+        offset.zone
+    }
+
+    fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<ZoneOffset> {
+        let fixed = match self {
+            Zone::Floating => Local.offset_from_local_date(local),
+            Zone::Fixed(offset) => LocalResult::Single(*offset),
+            Zone::Named(tz) => tz.offset_from_local_date(local).map(|offset| offset.fix()),
+        };
+        fixed.map(|fixed| ZoneOffset { zone: *self, fixed })
+    }
+
+    fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<ZoneOffset> {
+        let fixed = match self {
+            Zone::Floating => Local.offset_from_local_datetime(local),
+            Zone::Fixed(offset) => LocalResult::Single(*offset),
+            Zone::Named(tz) => tz.offset_from_local_datetime(local).map(|offset| offset.fix()),
+        };
+        fixed.map(|fixed| ZoneOffset { zone: *self, fixed })
+    }
+
+    fn offset_from_utc_date(&self, utc: &NaiveDate) -> ZoneOffset {
+        let fixed = match self {
+            Zone::Floating => Local.offset_from_utc_date(utc),
+            Zone::Fixed(offset) => *offset,
+            Zone::Named(tz) => tz.offset_from_utc_date(utc).fix(),
+        };
+        ZoneOffset { zone: *self, fixed }
+    }
+
+    fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> ZoneOffset {
+        let fixed = match self {
+            Zone::Floating => Local.offset_from_utc_datetime(utc),
+            Zone::Fixed(offset) => *offset,
+            Zone::Named(tz) => tz.offset_from_utc_datetime(utc).fix(),
+        };
+        ZoneOffset { zone: *self, fixed }
+    }
+}